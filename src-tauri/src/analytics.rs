@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use chrono::Local;
+use futures::TryStreamExt;
+use mongodb::bson::{self, doc, Document};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::billing::{local_day_bounds, round2};
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::session::{require_operator, SessionState};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SalesPeriod {
+    pub period: String, // First day of the bucket, YYYY-MM-DD in the store's timezone
+    pub bill_count: i64,
+    pub revenue: f64,   // Including tax
+    pub net_sales: f64, // Excluding tax
+    pub cost: f64,
+    pub gross_margin: f64,
+    pub margin_percent: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProductSales {
+    pub name: String,
+    pub quantity: i64,
+    pub revenue: f64,
+    pub cost: f64,
+    pub gross_margin: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SlowMover {
+    pub name: String,
+    pub stock_on_hand: i64,
+    pub quantity_sold: i64,
+    pub last_sold_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HourlySales {
+    pub hour: u32, // 0-23 in the store's timezone
+    pub bill_count: i64,
+    pub revenue: f64,
+}
+
+// Raw shapes returned by the pipelines below
+#[derive(Deserialize)]
+struct PeriodRow {
+    period: String,
+    bill_count: i64,
+    revenue: f64,
+    net_sales: f64,
+    cost: f64,
+}
+
+#[derive(Deserialize)]
+struct ProductRow {
+    #[serde(rename = "_id")]
+    name: String,
+    quantity: i64,
+    revenue: f64,
+    net_sales: f64,
+    cost: f64,
+    last_sold_at: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct HourRow {
+    #[serde(rename = "_id")]
+    hour: u32,
+    bill_count: i64,
+    revenue: f64,
+}
+
+#[derive(Deserialize)]
+struct StockRow {
+    #[serde(rename = "_id")]
+    name: String,
+    stock: i64,
+}
+
+// IANA name of the machine's timezone so MongoDB buckets by local days, including DST changes
+fn store_timezone() -> String {
+    iana_time_zone::get_timezone().unwrap_or_else(|_| Local::now().format("%:z").to_string())
+}
+
+fn bills_in_range(user_id: &str, from: &str, to: &str) -> Result<Document, AppError> {
+    let (start, _) = local_day_bounds(from)?;
+    let (_, end) = local_day_bounds(to)?;
+    Ok(doc! {
+        "$match": {
+            "user_id": user_id,
+            "created_at": { "$gte": start, "$lt": end }
+        }
+    })
+}
+
+async fn run_pipeline<T: for<'de> Deserialize<'de>>(
+    db: &Database,
+    collection: &str,
+    pipeline: Vec<Document>,
+) -> Result<Vec<T>, AppError> {
+    let collection_name = collection;
+    let collection: Collection<Document> = db.collection(collection);
+    let documents: Vec<Document> = collection
+        .aggregate(pipeline, None)
+        .await?
+        .try_collect()
+        .await?;
+
+    documents
+        .into_iter()
+        .map(|d| {
+            bson::from_document(d).map_err(|e| {
+                log::error!("Unexpected {} report row: {}", collection_name, e);
+                AppError::internal("Failed to read the report")
+            })
+        })
+        .collect()
+}
+
+// Sales of every product (grouped by name) between two dates
+async fn product_sales(db: &Database, user_id: &str, from: &str, to: &str) -> Result<Vec<ProductRow>, AppError> {
+    let pipeline = vec![
+        bills_in_range(user_id, from, to)?,
+        doc! { "$unwind": "$items" },
+        doc! {
+            "$group": {
+                "_id": "$items.name",
+                "quantity": { "$sum": "$items.quantity" },
+                "revenue": { "$sum": "$items.amount" },
+                "net_sales": { "$sum": { "$subtract": ["$items.amount", "$items.tax_amount"] } },
+                "cost": { "$sum": { "$multiply": ["$items.quantity", { "$ifNull": ["$items.unit_cost", 0.0] }] } },
+                "last_sold_at": { "$max": "$created_at" }
+            }
+        },
+    ];
+    run_pipeline(db, "bills", pipeline).await
+}
+
+// Revenue and gross margin per day, week (starting Monday) or month
+#[command]
+pub async fn get_sales_summary(
+    user_id: String,
+    from: String,
+    to: String,
+    interval: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<SalesPeriod>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    if !matches!(interval.as_str(), "day" | "week" | "month") {
+        return Err(AppError::validation("interval", format!("Unknown interval '{}', expected day, week or month", interval)));
+    }
+    let timezone = store_timezone();
+
+    let pipeline = vec![
+        bills_in_range(&user_id, &from, &to)?,
+        doc! {
+            "$project": {
+                "period": {
+                    "$dateTrunc": {
+                        "date": { "$toDate": "$created_at" },
+                        "unit": &interval,
+                        "timezone": &timezone,
+                        "startOfWeek": "monday"
+                    }
+                },
+                "total": 1,
+                "net": { "$subtract": ["$total", "$tax_total"] },
+                "cost": {
+                    "$sum": {
+                        "$map": {
+                            "input": "$items",
+                            "as": "item",
+                            "in": { "$multiply": ["$$item.quantity", { "$ifNull": ["$$item.unit_cost", 0.0] }] }
+                        }
+                    }
+                }
+            }
+        },
+        doc! {
+            "$group": {
+                "_id": "$period",
+                "bill_count": { "$sum": 1 },
+                "revenue": { "$sum": "$total" },
+                "net_sales": { "$sum": "$net" },
+                "cost": { "$sum": "$cost" }
+            }
+        },
+        doc! { "$sort": { "_id": 1 } },
+        doc! {
+            "$project": {
+                "_id": 0,
+                "period": { "$dateToString": { "date": "$_id", "format": "%Y-%m-%d", "timezone": &timezone } },
+                "bill_count": 1,
+                "revenue": 1,
+                "net_sales": 1,
+                "cost": 1
+            }
+        },
+    ];
+
+    let rows: Vec<PeriodRow> = run_pipeline(&db.db, "bills", pipeline).await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let gross_margin = round2(row.net_sales - row.cost);
+            SalesPeriod {
+                period: row.period,
+                bill_count: row.bill_count,
+                revenue: round2(row.revenue),
+                net_sales: round2(row.net_sales),
+                cost: round2(row.cost),
+                gross_margin,
+                margin_percent: if row.net_sales > 0.0 {
+                    round2(gross_margin / row.net_sales * 100.0)
+                } else {
+                    0.0
+                },
+            }
+        })
+        .collect())
+}
+
+// Best-selling products ranked by quantity or by value
+#[command]
+pub async fn get_top_products(
+    user_id: String,
+    from: String,
+    to: String,
+    rank_by: String,
+    limit: u32,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<ProductSales>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let mut rows = product_sales(&db.db, &user_id, &from, &to).await?;
+    match rank_by.as_str() {
+        "quantity" => rows.sort_by_key(|row| std::cmp::Reverse(row.quantity)),
+        "value" => rows.sort_by(|a, b| b.revenue.total_cmp(&a.revenue)),
+        other => {
+            let message = format!("Cannot rank products by '{}', expected quantity or value", other);
+            return Err(AppError::validation("rank_by", message));
+        }
+    }
+
+    Ok(rows
+        .into_iter()
+        .take(limit as usize)
+        .map(|row| ProductSales {
+            name: row.name,
+            quantity: row.quantity,
+            revenue: round2(row.revenue),
+            cost: round2(row.cost),
+            gross_margin: round2(row.net_sales - row.cost),
+        })
+        .collect())
+}
+
+// Products with stock on hand that sold the least (or not at all) in the period
+#[command]
+pub async fn get_slow_movers(
+    user_id: String,
+    from: String,
+    to: String,
+    limit: u32,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<SlowMover>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let sold: HashMap<String, ProductRow> = product_sales(&db.db, &user_id, &from, &to)
+        .await?
+        .into_iter()
+        .map(|row| (row.name.clone(), row))
+        .collect();
+
+    let pipeline = vec![
+        doc! { "$match": { "user_id": &user_id, "deleted_at": null, "quantity": { "$gt": 0 } } },
+        doc! { "$group": { "_id": "$name", "stock": { "$sum": "$quantity" } } },
+    ];
+    let stock: Vec<StockRow> = run_pipeline(&db.db, "medicines", pipeline).await?;
+
+    let mut movers: Vec<SlowMover> = stock
+        .into_iter()
+        .map(|row| {
+            let sales = sold.get(&row.name);
+            SlowMover {
+                quantity_sold: sales.map_or(0, |s| s.quantity),
+                last_sold_at: sales.and_then(|s| s.last_sold_at),
+                stock_on_hand: row.stock,
+                name: row.name,
+            }
+        })
+        .collect();
+    movers.sort_by(|a, b| {
+        a.quantity_sold
+            .cmp(&b.quantity_sold)
+            .then(b.stock_on_hand.cmp(&a.stock_on_hand))
+    });
+    movers.truncate(limit as usize);
+
+    Ok(movers)
+}
+
+// Bills and revenue per hour of the day, always returning all 24 hours
+#[command]
+pub async fn get_sales_by_hour(
+    user_id: String,
+    from: String,
+    to: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<HourlySales>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let pipeline = vec![
+        bills_in_range(&user_id, &from, &to)?,
+        doc! {
+            "$group": {
+                "_id": { "$hour": { "date": { "$toDate": "$created_at" }, "timezone": store_timezone() } },
+                "bill_count": { "$sum": 1 },
+                "revenue": { "$sum": "$total" }
+            }
+        },
+    ];
+    let rows: Vec<HourRow> = run_pipeline(&db.db, "bills", pipeline).await?;
+
+    let mut hours: Vec<HourlySales> = (0..24)
+        .map(|hour| HourlySales { hour, bill_count: 0, revenue: 0.0 })
+        .collect();
+    for row in rows {
+        if let Some(slot) = hours.get_mut(row.hour as usize) {
+            slot.bill_count = row.bill_count;
+            slot.revenue = round2(row.revenue);
+        }
+    }
+
+    Ok(hours)
+}
@@ -0,0 +1,624 @@
+use chrono::{Local, NaiveDate, TimeZone};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, DateTime, Document};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::commands::Medicine;
+use crate::customers::{find_customer, post_charge, release_credit, reserve_credit};
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::ledger::{record_movements, StockMovement};
+use crate::session::{require_operator, SessionState};
+use crate::settings::load_store_settings;
+use crate::user::PRIVILEGED_ROLES;
+
+// Amounts closer than this are treated as equal when settling a bill
+const PAYMENT_EPSILON: f64 = 0.005;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentMode {
+    Cash,
+    Card,
+    Upi,
+    Credit, // Posted on the customer's account, settled later
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Payment {
+    pub mode: PaymentMode,
+    pub amount: f64, // For cash this is the amount tendered, change is kept on the bill
+    pub reference: Option<String>, // Card approval code, UPI transaction id, etc.
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BillItem {
+    pub medicine_id: String,
+    pub name: String,
+    pub batch_number: String,
+    pub quantity: u32,
+    pub unit_price: f64,
+    #[serde(default)]
+    pub unit_cost: f64, // Batch purchase price at the time of sale, used for margins
+    pub tax_rate: f64, // Percentage, e.g. 12.0 for 12% GST
+    pub tax_amount: f64,
+    pub amount: f64, // Line total including tax
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bill {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub bill_number: String,
+    pub customer_id: Option<String>, // Set when part of the bill is on account
+    pub customer_name: String,
+    pub patient_name: Option<String>,
+    #[serde(default)]
+    pub billed_by: Option<String>, // Pharmacist who made the sale, as printed on the bill
+    #[serde(default)]
+    pub billed_by_user_id: Option<String>,
+    pub items: Vec<BillItem>,
+    pub subtotal: f64,
+    pub tax_total: f64,
+    #[serde(default)]
+    pub round_off: f64, // Added to subtotal plus tax to reach the rounded total
+    pub total: f64,
+    pub payments: Vec<Payment>,
+    pub change_due: f64,
+    pub created_at: i64, // Milliseconds since the Unix epoch
+    #[serde(default)]
+    pub reprint_count: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BillItemInput {
+    pub medicine_id: String,
+    pub quantity: u32,
+    pub tax_rate: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BillFilter {
+    pub from: Option<String>, // Local dates, both inclusive
+    pub to: Option<String>,
+    pub customer: Option<String>, // Case-insensitive match on the customer name
+    pub patient: Option<String>,
+    pub payment_mode: Option<PaymentMode>,
+    pub min_total: Option<f64>,
+    pub max_total: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BillPage {
+    pub bills: Vec<Bill>,
+    pub total_count: u64,
+    pub page: u32,
+    pub limit: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CashDrawerReport {
+    pub user_id: String,
+    pub date: String,
+    pub bill_count: u32,
+    pub opening_float: f64,
+    pub cash_sales: f64, // Cash tendered minus change given back
+    pub expected_cash: f64,
+    pub counted_cash: f64,
+    pub difference: f64, // Positive means the drawer is over
+    pub closed_at: i64,
+}
+
+pub fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+// Escape user input so it matches literally inside a `$regex`
+pub fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Atomically increment and return a named per-user counter
+pub async fn next_sequence(db: &Database, user_id: &str, name: &str) -> Result<i64, AppError> {
+    let counters: Collection<Document> = db.collection("counters");
+    let options = FindOneAndUpdateOptions::builder()
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .build();
+
+    let counter = counters
+        .find_one_and_update(
+            doc! { "user_id": user_id, "name": name },
+            doc! { "$inc": { "value": 1_i64 } },
+            options,
+        )
+        .await?
+        .ok_or_else(|| AppError::internal("Failed to allocate sequence number"))?;
+
+    counter
+        .get_i64("value")
+        .map_err(|_| AppError::internal("Failed to allocate sequence number"))
+}
+
+// Convert a local calendar date into a [start, end) range of epoch milliseconds
+pub fn local_day_bounds(date: &str) -> Result<(i64, i64), AppError> {
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| AppError::validation("date", format!("Invalid date '{}', expected YYYY-MM-DD", date)))?;
+    let to_millis = |d: NaiveDate| {
+        d.and_hms_opt(0, 0, 0)
+            .and_then(|t| t.and_local_timezone(Local).earliest())
+            .map(|t| t.timestamp_millis())
+            .ok_or_else(|| AppError::validation("date", format!("Date '{}' does not exist in the local timezone", d)))
+    };
+    let next = day.succ_opt().ok_or_else(|| AppError::validation("date", "Date out of range"))?;
+    Ok((to_millis(day)?, to_millis(next)?))
+}
+
+// Calendar date, in the local timezone, of an epoch-millisecond timestamp
+pub fn format_local_date(millis: i64) -> String {
+    Local
+        .timestamp_millis_opt(millis)
+        .single()
+        .map(|t| t.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+// Check that the payments cover the total and work out the change to return
+fn settle_payments(total: f64, payments: &[Payment]) -> Result<f64, String> {
+    if payments.is_empty() {
+        return Err("At least one payment is required".to_string());
+    }
+
+    let mut cash = 0.0;
+    let mut non_cash = 0.0;
+    for payment in payments {
+        if !payment.amount.is_finite() || payment.amount <= 0.0 {
+            return Err("Payment amounts must be greater than zero".to_string());
+        }
+        match payment.mode {
+            PaymentMode::Cash => cash += payment.amount,
+            _ => non_cash += payment.amount,
+        }
+    }
+
+    // Change can only be given in cash, so card/UPI/credit may not exceed the bill
+    if non_cash > total + PAYMENT_EPSILON {
+        return Err("Card, UPI and credit payments cannot exceed the bill total".to_string());
+    }
+    if cash + non_cash < total - PAYMENT_EPSILON {
+        return Err(format!(
+            "Payments of {:.2} do not cover the bill total of {:.2}",
+            cash + non_cash,
+            total
+        ));
+    }
+
+    Ok(round2((cash + non_cash - total).max(0.0)))
+}
+
+// Put back stock taken for lines that were already processed
+async fn restore_stock(collection: &Collection<Medicine>, user_id: &str, items: &[BillItem]) {
+    for item in items {
+        if let Ok(object_id) = ObjectId::parse_str(&item.medicine_id) {
+            let _ = collection
+                .update_one(
+                    doc! { "_id": object_id, "user_id": user_id },
+                    doc! { "$inc": { "quantity": item.quantity as i64, "version": 1_i64 } },
+                    None,
+                )
+                .await;
+        }
+    }
+}
+
+// Create a bill, deduct the sold quantities and record how it was paid.
+// Credit payments are posted to the customer's account and must fit in their
+// credit limit unless an owner or manager overrides it.
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub async fn create_bill(
+    user_id: String,
+    customer_name: String,
+    customer_id: Option<String>,
+    patient_name: Option<String>,
+    items: Vec<BillItemInput>,
+    payments: Vec<Payment>,
+    override_credit_limit: Option<bool>,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Bill, AppError> {
+    if items.is_empty() {
+        return Err(AppError::validation("items", "A bill needs at least one item"));
+    }
+
+    let on_account = round2(
+        payments
+            .iter()
+            .filter(|p| p.mode == PaymentMode::Credit)
+            .map(|p| p.amount)
+            .sum(),
+    );
+    let mut customer_name = customer_name.trim().to_string();
+    if on_account > 0.0 {
+        let customer_id = customer_id
+            .as_deref()
+            .ok_or_else(|| AppError::validation("customer_id", "Credit payments require a customer account"))?;
+        let customer = find_customer(&db.db, &user_id, customer_id).await?;
+        if customer_name.is_empty() {
+            customer_name = customer.name;
+        }
+    }
+
+    // The bill belongs to the store but is signed by whoever has the terminal unlocked
+    let operator = require_operator(&db.db, &session, &user_id).await?;
+    let allow_over_limit = if override_credit_limit.unwrap_or(false) && on_account > 0.0 {
+        if !PRIVILEGED_ROLES.contains(&operator.role.as_str()) {
+            return Err(AppError::forbidden("Only an owner or manager can override a credit limit"));
+        }
+        true
+    } else {
+        false
+    };
+
+    let settings = load_store_settings(&db.db, &user_id).await?;
+    let operator_id = operator.id.map(|id| id.to_hex()).unwrap_or_default();
+    let billed_by = operator.signature();
+    let medicines: Collection<Medicine> = db.db.collection("medicines");
+    let mut bill_items: Vec<BillItem> = Vec::new();
+    let mut movements: Vec<StockMovement> = Vec::new();
+
+    for input in &items {
+        if input.quantity == 0 {
+            restore_stock(&medicines, &user_id, &bill_items).await;
+            return Err(AppError::validation("items", "Item quantities must be greater than zero"));
+        }
+        let object_id = match ObjectId::parse_str(&input.medicine_id) {
+            Ok(id) => id,
+            Err(e) => {
+                restore_stock(&medicines, &user_id, &bill_items).await;
+                return Err(e.into());
+            }
+        };
+
+        // Only decrement when enough stock is left, so concurrent sales cannot oversell
+        let filter = doc! {
+            "_id": object_id,
+            "user_id": &user_id,
+            "deleted_at": null,
+            "quantity": { "$gte": input.quantity as i64 }
+        };
+        let update = doc! { "$inc": { "quantity": -(input.quantity as i64), "version": 1_i64 } };
+        let medicine = match medicines.find_one_and_update(filter, update, None).await {
+            Ok(Some(medicine)) => medicine,
+            Ok(None) => {
+                restore_stock(&medicines, &user_id, &bill_items).await;
+                return Err(stock_shortage(&medicines, &user_id, object_id, &input.medicine_id).await);
+            }
+            Err(e) => {
+                restore_stock(&medicines, &user_id, &bill_items).await;
+                return Err(e.into());
+            }
+        };
+
+        movements.push(StockMovement::for_batch(&medicine, -(input.quantity as i64), "sale", None));
+        let tax_rate = input.tax_rate.unwrap_or(settings.default_tax_rate);
+        let net = round2(medicine.selling_price * input.quantity as f64);
+        let tax_amount = round2(net * tax_rate / 100.0);
+        bill_items.push(BillItem {
+            medicine_id: input.medicine_id.clone(),
+            name: medicine.name,
+            batch_number: medicine.batch_number,
+            quantity: input.quantity,
+            unit_price: medicine.selling_price,
+            unit_cost: medicine.purchase_price,
+            tax_rate,
+            tax_amount,
+            amount: round2(net + tax_amount),
+        });
+    }
+
+    let subtotal = round2(bill_items.iter().map(|i| i.amount - i.tax_amount).sum());
+    let tax_total = round2(bill_items.iter().map(|i| i.tax_amount).sum());
+    let total = settings.round_total(subtotal + tax_total);
+    let round_off = round2(total - subtotal - tax_total);
+
+    let change_due = match settle_payments(total, &payments) {
+        Ok(change) => change,
+        Err(e) => {
+            restore_stock(&medicines, &user_id, &bill_items).await;
+            return Err(AppError::validation("payments", e));
+        }
+    };
+
+    let credit_customer = if on_account > 0.0 { customer_id.clone() } else { None };
+    if let Some(customer_id) = &credit_customer {
+        if let Err(e) = reserve_credit(&db.db, &user_id, customer_id, on_account, allow_over_limit).await {
+            restore_stock(&medicines, &user_id, &bill_items).await;
+            return Err(e);
+        }
+    }
+
+    let sequence = match next_sequence(&db.db, &user_id, "bill").await {
+        Ok(sequence) => sequence,
+        Err(e) => {
+            restore_stock(&medicines, &user_id, &bill_items).await;
+            if let Some(customer_id) = &credit_customer {
+                release_credit(&db.db, &user_id, customer_id, on_account).await;
+            }
+            return Err(e);
+        }
+    };
+
+    let mut bill = Bill {
+        id: None,
+        user_id: user_id.clone(),
+        bill_number: format!("B{:06}", sequence),
+        customer_id: credit_customer.clone().or(customer_id),
+        customer_name,
+        patient_name: patient_name
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty()),
+        billed_by: Some(billed_by),
+        billed_by_user_id: Some(operator_id),
+        items: bill_items,
+        subtotal,
+        tax_total,
+        round_off,
+        total,
+        payments,
+        change_due,
+        created_at: DateTime::now().timestamp_millis(),
+        reprint_count: 0,
+    };
+
+    let bills: Collection<Bill> = db.db.collection("bills");
+    match bills.insert_one(&bill, None).await {
+        Ok(result) => bill.id = result.inserted_id.as_object_id(),
+        Err(e) => {
+            restore_stock(&medicines, &user_id, &bill.items).await;
+            if let Some(customer_id) = &credit_customer {
+                release_credit(&db.db, &user_id, customer_id, on_account).await;
+            }
+            return Err(e.into());
+        }
+    }
+
+    // The bill is saved and the stock is gone, so from here on a failure must not reach
+    // the caller as an error: a retry would bill the customer twice. It is logged for
+    // the ledger or account to be corrected by hand instead.
+    for movement in movements.iter_mut() {
+        movement.reference = Some(bill.bill_number.clone());
+    }
+    if let Err(e) = record_movements(&db.db, movements).await {
+        log::error!("Bill {} was saved but its stock movements were not recorded: {}", bill.bill_number, e);
+    }
+
+    if let Some(customer_id) = &credit_customer {
+        if let Err(e) = post_charge(&db.db, &user_id, customer_id, &bill.bill_number, on_account, bill.created_at).await {
+            log::error!(
+                "Bill {} was saved but the charge of {:.2} to customer {} was not posted: {}",
+                bill.bill_number, on_account, customer_id, e
+            );
+        }
+    }
+
+    Ok(bill)
+}
+
+// Explain why a stock decrement matched nothing: the batch is missing or too low
+async fn stock_shortage(
+    medicines: &Collection<Medicine>,
+    user_id: &str,
+    object_id: ObjectId,
+    medicine_id: &str,
+) -> AppError {
+    match medicines.find_one(doc! { "_id": object_id, "user_id": user_id, "deleted_at": null }, None).await {
+        Ok(Some(medicine)) => AppError::InsufficientStock {
+            message: format!("Only {} of {} left in stock", medicine.quantity, medicine.name),
+            medicine_id: medicine_id.to_string(),
+            available: medicine.quantity,
+        },
+        Ok(None) => AppError::not_found("No medicine found for the specified user and ID."),
+        Err(e) => e.into(),
+    }
+}
+
+// Compare the cash the day's bills should have left in the drawer with the counted amount
+#[command]
+pub async fn reconcile_cash_drawer(
+    user_id: String,
+    date: String,
+    opening_float: f64,
+    counted_cash: f64,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<CashDrawerReport, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    if !opening_float.is_finite() || opening_float < 0.0 || !counted_cash.is_finite() || counted_cash < 0.0 {
+        return Err(AppError::validation("counted_cash", "Opening float and counted cash must be non-negative amounts"));
+    }
+
+    let (start, end) = local_day_bounds(&date)?;
+    let bills: Collection<Bill> = db.db.collection("bills");
+    let filter = doc! {
+        "user_id": &user_id,
+        "created_at": { "$gte": start, "$lt": end }
+    };
+    let day_bills: Vec<Bill> = bills
+        .find(filter, None)
+        .await?
+        .try_collect()
+        .await?;
+
+    let cash_sales: f64 = day_bills
+        .iter()
+        .map(|bill| {
+            let tendered: f64 = bill
+                .payments
+                .iter()
+                .filter(|p| p.mode == PaymentMode::Cash)
+                .map(|p| p.amount)
+                .sum();
+            tendered - bill.change_due
+        })
+        .sum();
+
+    let expected_cash = round2(opening_float + cash_sales);
+    let report = CashDrawerReport {
+        user_id,
+        date,
+        bill_count: day_bills.len() as u32,
+        opening_float: round2(opening_float),
+        cash_sales: round2(cash_sales),
+        expected_cash,
+        counted_cash: round2(counted_cash),
+        difference: round2(counted_cash - expected_cash),
+        closed_at: DateTime::now().timestamp_millis(),
+    };
+
+    let closings: Collection<CashDrawerReport> = db.db.collection("cash_drawer_closings");
+    closings
+        .insert_one(&report, None)
+        .await?;
+
+    Ok(report)
+}
+
+// Search stored bills with filters, sorting and page-based pagination
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub async fn list_bills(
+    user_id: String,
+    filter: Option<BillFilter>,
+    page: u32,
+    limit: u32,
+    sort_by: Option<String>,
+    descending: Option<bool>,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<BillPage, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    if page == 0 || limit == 0 {
+        return Err(AppError::validation("page", "Page and limit must be at least 1"));
+    }
+    let filter = filter.unwrap_or_default();
+
+    let mut query = doc! { "user_id": &user_id };
+    let mut created_at = Document::new();
+    if let Some(from) = &filter.from {
+        created_at.insert("$gte", local_day_bounds(from)?.0);
+    }
+    if let Some(to) = &filter.to {
+        created_at.insert("$lt", local_day_bounds(to)?.1);
+    }
+    if !created_at.is_empty() {
+        query.insert("created_at", created_at);
+    }
+    if let Some(customer) = filter.customer.as_deref().map(str::trim).filter(|c| !c.is_empty()) {
+        query.insert("customer_name", doc! { "$regex": escape_regex(customer), "$options": "i" });
+    }
+    if let Some(patient) = filter.patient.as_deref().map(str::trim).filter(|p| !p.is_empty()) {
+        query.insert("patient_name", doc! { "$regex": escape_regex(patient), "$options": "i" });
+    }
+    if let Some(mode) = filter.payment_mode {
+        let mode = mongodb::bson::to_bson(&mode).map_err(|_| AppError::validation("payment_mode", "Unknown payment mode"))?;
+        query.insert("payments.mode", mode);
+    }
+    let mut total = Document::new();
+    if let Some(min) = filter.min_total {
+        total.insert("$gte", min);
+    }
+    if let Some(max) = filter.max_total {
+        total.insert("$lte", max);
+    }
+    if !total.is_empty() {
+        query.insert("total", total);
+    }
+
+    let sort_field = match sort_by.as_deref().unwrap_or("created_at") {
+        field @ ("created_at" | "total" | "bill_number" | "customer_name") => field,
+        other => return Err(AppError::validation("sort_by", format!("Cannot sort bills by '{}'", other))),
+    };
+    let direction = if descending.unwrap_or(true) { -1 } else { 1 };
+
+    let bills: Collection<Bill> = db.db.collection("bills");
+    let total_count = bills
+        .count_documents(query.clone(), None)
+        .await?;
+
+    // `_id` breaks ties so rows do not shuffle between pages
+    let options = FindOptions::builder()
+        .sort(doc! { sort_field: direction, "_id": direction })
+        .skip(((page - 1) as u64) * limit as u64)
+        .limit(limit as i64)
+        .build();
+    let page_bills: Vec<Bill> = bills
+        .find(query, options)
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(BillPage {
+        bills: page_bills,
+        total_count,
+        page,
+        limit,
+    })
+}
+
+pub async fn find_bill(db: &Database, user_id: &str, bill_id: &str) -> Result<Bill, AppError> {
+    let bills: Collection<Bill> = db.collection("bills");
+    let object_id = ObjectId::parse_str(bill_id)?;
+    bills
+        .find_one(doc! { "_id": object_id, "user_id": user_id }, None)
+        .await?
+        .ok_or_else(|| AppError::not_found("No bill found for the specified user and ID."))
+}
+
+// Retrieve a single stored bill
+#[command]
+pub async fn get_bill(
+    user_id: String,
+    bill_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Bill, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    find_bill(&db.db, &user_id, &bill_id).await
+}
+
+// Return a bill for printing again. Lines, prices, taxes and the number are the
+// ones saved at the time of sale, so later price changes do not affect the copy.
+#[command]
+pub async fn reprint_bill(
+    user_id: String,
+    bill_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Bill, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let bills: Collection<Bill> = db.db.collection("bills");
+    let object_id = ObjectId::parse_str(&bill_id)?;
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+
+    bills
+        .find_one_and_update(
+            doc! { "_id": object_id, "user_id": &user_id },
+            doc! { "$inc": { "reprint_count": 1 } },
+            options,
+        )
+        .await?
+        .ok_or_else(|| AppError::not_found("No bill found for the specified user and ID."))
+}
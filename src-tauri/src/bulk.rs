@@ -0,0 +1,526 @@
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::FindOptions;
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::billing::{escape_regex, round2};
+use crate::commands::{version_filter, Medicine};
+use crate::db::DbState;
+use crate::error::{AppError, FieldError};
+use crate::ledger::{record_movements, StockMovement};
+use crate::session::{require_operator, require_role, SessionState};
+use crate::settings::load_store_settings;
+use crate::user::PRIVILEGED_ROLES;
+use crate::validation::{validate_medicine, MedicineRules};
+
+// Which batches a bulk operation applies to. Every given criterion must match.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BulkFilter {
+    pub manufacturer: Option<String>,
+    pub category: Option<String>,
+    pub supplier: Option<String>, // Wholesaler the batch was bought from
+    pub name: Option<String>, // Part of the product name, case-insensitive
+    pub medicine_ids: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BulkAction {
+    PricePercent { percent: f64 }, // e.g. 5 for a 5% rise, -10 for a 10% cut
+    PriceAmount { amount: f64 }, // Added to the selling price, negative to lower it
+    AdjustQuantity { change: i64 },
+    Archive, // Move the batches to the trash
+}
+
+// One batch before and after a bulk operation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkChange {
+    pub medicine_id: String,
+    pub name: String,
+    pub batch_number: String,
+    pub selling_price_before: f64,
+    pub selling_price_after: f64,
+    pub quantity_before: u32,
+    pub quantity_after: u32,
+    pub version_after: i64, // Reverting only touches batches nobody has saved since
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkPreview {
+    pub changes: Vec<BulkChange>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkOperation {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub filter: BulkFilter,
+    pub action: BulkAction,
+    pub changes: Vec<BulkChange>,
+    pub performed_by: String,
+    pub created_at: i64,
+    #[serde(default = "default_status")]
+    pub status: String, // "pending" while it runs, then "applied" or "failed"
+    #[serde(default)]
+    pub reverted_at: Option<i64>,
+    #[serde(default)]
+    pub reverted_by: Option<String>,
+}
+
+// Operations saved before they had a status were only saved once applied
+fn default_status() -> String {
+    "applied".to_string()
+}
+
+fn filter_document(user_id: &str, filter: &BulkFilter) -> Result<Document, AppError> {
+    let mut query = doc! { "user_id": user_id, "deleted_at": null };
+    let trimmed = |value: &Option<String>| value.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(String::from);
+    if let Some(manufacturer) = trimmed(&filter.manufacturer) {
+        query.insert("manufacturer", manufacturer);
+    }
+    if let Some(category) = trimmed(&filter.category) {
+        query.insert("category", category);
+    }
+    if let Some(supplier) = trimmed(&filter.supplier) {
+        query.insert("wholesaler_name", supplier);
+    }
+    if let Some(name) = trimmed(&filter.name) {
+        query.insert("name", doc! { "$regex": escape_regex(&name), "$options": "i" });
+    }
+    if let Some(ids) = &filter.medicine_ids {
+        let ids = ids.iter().map(ObjectId::parse_str).collect::<Result<Vec<_>, _>>()?;
+        query.insert("_id", doc! { "$in": ids });
+    }
+    // Refuse to touch the whole store by accident
+    if query.len() == 2 {
+        return Err(AppError::validation("filter", "Give at least one filter for a bulk operation"));
+    }
+    Ok(query)
+}
+
+// Work out what the operation would do to each matching batch, rejecting it
+// as a whole if any batch would end up invalid
+async fn plan(
+    db: &Database,
+    user_id: &str,
+    filter: &BulkFilter,
+    action: &BulkAction,
+) -> Result<(Vec<BulkChange>, Vec<String>), AppError> {
+    match action {
+        BulkAction::PricePercent { percent } if !percent.is_finite() || *percent <= -100.0 => {
+            return Err(AppError::validation("percent", "Percentage must be greater than -100"));
+        }
+        BulkAction::PriceAmount { amount } if !amount.is_finite() => {
+            return Err(AppError::validation("amount", "Amount must be a number"));
+        }
+        BulkAction::AdjustQuantity { change: 0 } => {
+            return Err(AppError::validation("change", "Quantity change cannot be zero"));
+        }
+        _ => {}
+    }
+
+    let collection: Collection<Medicine> = db.collection("medicines");
+    let options = FindOptions::builder().sort(doc! { "name": 1, "batch_number": 1 }).build();
+    let medicines: Vec<Medicine> = collection
+        .find(filter_document(user_id, filter)?, options)
+        .await?
+        .try_collect()
+        .await?;
+    if medicines.is_empty() {
+        return Err(AppError::not_found("No medicines match the filter."));
+    }
+
+    let rules = MedicineRules::from(&load_store_settings(db, user_id).await?);
+    let mut changes = Vec::new();
+    let mut warnings = Vec::new();
+    let mut errors: Vec<FieldError> = Vec::new();
+    for medicine in medicines {
+        let mut after = medicine.clone();
+        match action {
+            BulkAction::PricePercent { percent } => {
+                after.selling_price = round2(medicine.selling_price * (1.0 + percent / 100.0));
+            }
+            BulkAction::PriceAmount { amount } => after.selling_price = round2(medicine.selling_price + amount),
+            BulkAction::AdjustQuantity { change } => {
+                let quantity = medicine.quantity as i64 + change;
+                if quantity < 0 || quantity > u32::MAX as i64 {
+                    errors.push(FieldError {
+                        field: medicine.id.map(|id| id.to_hex()).unwrap_or_default(),
+                        message: format!(
+                            "{} batch {} has only {} in stock",
+                            medicine.name, medicine.batch_number, medicine.quantity
+                        ),
+                    });
+                    continue;
+                }
+                after.quantity = quantity as u32;
+            }
+            BulkAction::Archive => {}
+        }
+
+        // Only a new price can make a batch invalid
+        if matches!(action, BulkAction::PricePercent { .. } | BulkAction::PriceAmount { .. }) {
+            let label = format!("{} batch {}", medicine.name, medicine.batch_number);
+            match validate_medicine(&after, &rules) {
+                Ok(found) => warnings.extend(found.into_iter().map(|w| format!("{}: {}", label, w))),
+                Err(AppError::Validation { fields, .. }) => errors.extend(fields.into_iter().map(|f| FieldError {
+                    field: medicine.id.map(|id| id.to_hex()).unwrap_or_default(),
+                    message: format!("{}: {}", label, f.message),
+                })),
+                Err(e) => return Err(e),
+            }
+        }
+
+        changes.push(BulkChange {
+            medicine_id: medicine.id.map(|id| id.to_hex()).unwrap_or_default(),
+            name: medicine.name,
+            batch_number: medicine.batch_number,
+            selling_price_before: medicine.selling_price,
+            selling_price_after: after.selling_price,
+            quantity_before: medicine.quantity,
+            quantity_after: after.quantity,
+            version_after: medicine.version + 1,
+        });
+    }
+
+    if !errors.is_empty() {
+        return Err(AppError::Validation {
+            message: format!("{} batch(es) cannot be changed this way", errors.len()),
+            fields: errors,
+        });
+    }
+    Ok((changes, warnings))
+}
+
+// The update that takes a batch from its `before` to its `after` state, or back
+fn change_update(change: &BulkChange, action: &BulkAction, forward: bool, actor: &str) -> Document {
+    let (price, quantity) = if forward {
+        (change.selling_price_after, change.quantity_after)
+    } else {
+        (change.selling_price_before, change.quantity_before)
+    };
+    match (action, forward) {
+        (BulkAction::Archive, true) => doc! {
+            "$set": { "deleted_at": Utc::now().timestamp_millis(), "deleted_by": actor },
+            "$inc": { "version": 1_i64 }
+        },
+        (BulkAction::Archive, false) => doc! {
+            "$unset": { "deleted_at": "", "deleted_by": "" },
+            "$inc": { "version": 1_i64 }
+        },
+        _ => doc! {
+            "$set": { "selling_price": price, "quantity": quantity as i64 },
+            "$inc": { "version": 1_i64 }
+        },
+    }
+}
+
+// Ledger entries for applying or reverting an operation, all carrying the same reference
+fn change_movements(
+    medicines: &[Medicine],
+    changes: &[BulkChange],
+    action: &BulkAction,
+    forward: bool,
+    reference: &str,
+) -> Vec<StockMovement> {
+    medicines
+        .iter()
+        .zip(changes)
+        .map(|(medicine, change)| {
+            let (change, reason) = match (action, forward) {
+                (BulkAction::Archive, true) => (-(change.quantity_before as i64), "delete"),
+                (BulkAction::Archive, false) => (change.quantity_before as i64, "restore"),
+                _ if forward => (change.quantity_after as i64 - change.quantity_before as i64, "adjustment"),
+                _ => (change.quantity_before as i64 - change.quantity_after as i64, "adjustment"),
+            };
+            StockMovement::for_batch(medicine, change, reason, Some(reference.to_string()))
+        })
+        .collect()
+}
+
+// The ID of the operator at the terminal, who must be an owner or manager of the store
+async fn require_privileged(db: &Database, session: &SessionState, user_id: &str) -> Result<String, AppError> {
+    let denied = "Only an owner or manager can run bulk operations";
+    let operator = require_role(db, session, user_id, &PRIVILEGED_ROLES, denied).await?;
+    Ok(operator.id.map(|id| id.to_hex()).unwrap_or_default())
+}
+
+// Show which batches a bulk operation would change and how, without changing anything
+#[command]
+pub async fn preview_bulk_operation(
+    user_id: String,
+    filter: BulkFilter,
+    action: BulkAction,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<BulkPreview, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let (changes, warnings) = plan(&db.db, &user_id, &filter, &action).await?;
+    Ok(BulkPreview { changes, warnings })
+}
+
+// Apply a bulk operation to every matching batch. The operation is saved as pending
+// before any batch changes, so a crash part-way leaves a record of what was started.
+// If any batch is saved by someone else while it runs, or its ledger entries cannot be
+// written, the batches already changed are put back and the operation is marked failed.
+#[command]
+pub async fn apply_bulk_operation(
+    user_id: String,
+    filter: BulkFilter,
+    action: BulkAction,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<BulkOperation, AppError> {
+    let actor = require_privileged(&db.db, &session, &user_id).await?;
+    let (changes, _) = plan(&db.db, &user_id, &filter, &action).await?;
+
+    let id = ObjectId::new();
+    let mut operation = BulkOperation {
+        id: Some(id),
+        user_id: user_id.clone(),
+        filter,
+        action,
+        changes,
+        performed_by: actor.clone(),
+        created_at: Utc::now().timestamp_millis(),
+        status: "pending".to_string(),
+        reverted_at: None,
+        reverted_by: None,
+    };
+    let operations: Collection<BulkOperation> = db.db.collection("bulk_operations");
+    operations.insert_one(&operation, None).await?;
+
+    let collection: Collection<Medicine> = db.db.collection("medicines");
+    let changes = &operation.changes;
+    let action = &operation.action;
+    let mut applied: Vec<Medicine> = Vec::new();
+    let mut failure: Option<AppError> = None;
+    for change in changes {
+        let object_id = ObjectId::parse_str(&change.medicine_id)?;
+        let query = doc! {
+            "_id": object_id,
+            "user_id": &user_id,
+            "deleted_at": null,
+            "version": version_filter(change.version_after - 1),
+        };
+        match collection.find_one_and_update(query, change_update(change, action, true, &actor), None).await {
+            Ok(Some(medicine)) => applied.push(medicine),
+            Ok(None) => {
+                failure = Some(AppError::conflict(format!(
+                    "{} batch {} was changed by someone else. Preview the operation again.",
+                    change.name, change.batch_number
+                )));
+                break;
+            }
+            Err(e) => {
+                failure = Some(e.into());
+                break;
+            }
+        }
+    }
+    if failure.is_none() {
+        let reference = format!("bulk {}", id.to_hex());
+        if let Err(e) = record_movements(&db.db, change_movements(&applied, changes, action, true, &reference)).await {
+            failure = Some(e);
+        }
+    }
+
+    if let Some(error) = failure {
+        let stuck = rollback(&collection, &user_id, &changes[..applied.len()], action, false, &actor).await;
+        if let Err(e) = set_status(&operations, id, "failed").await {
+            log::error!("Failed to mark bulk operation {} as failed: {}", id.to_hex(), e);
+        }
+        if stuck.is_empty() {
+            return Err(error);
+        }
+        log::error!("Bulk operation {} could not put back: {}", id.to_hex(), stuck.join(", "));
+        return Err(AppError::internal(format!(
+            "{} The operation was undone except for {}, which need checking by hand.",
+            error,
+            stuck.join(", ")
+        )));
+    }
+
+    // Every batch is changed and recorded; a failure here only leaves the status behind
+    match set_status(&operations, id, "applied").await {
+        Ok(_) => operation.status = "applied".to_string(),
+        Err(e) => log::error!("Bulk operation {} was applied but is still marked pending: {}", id.to_hex(), e),
+    }
+    Ok(operation)
+}
+
+async fn set_status(
+    operations: &Collection<BulkOperation>,
+    id: ObjectId,
+    status: &str,
+) -> Result<(), mongodb::error::Error> {
+    operations
+        .update_one(doc! { "_id": id }, doc! { "$set": { "status": status } }, None)
+        .await?;
+    Ok(())
+}
+
+// Put back batches changed before an apply (or, with `reapply`, a revert) failed part-way.
+// Returns the batches that could not be put back, because they were saved again meanwhile
+// or the update failed.
+async fn rollback(
+    collection: &Collection<Medicine>,
+    user_id: &str,
+    changes: &[BulkChange],
+    action: &BulkAction,
+    reapply: bool,
+    actor: &str,
+) -> Vec<String> {
+    let mut stuck = Vec::new();
+    for change in changes {
+        // A reverted batch is one version past the operation
+        let version = if reapply { change.version_after + 1 } else { change.version_after };
+        let put_back = match ObjectId::parse_str(&change.medicine_id) {
+            Ok(object_id) => collection
+                .update_one(
+                    doc! { "_id": object_id, "user_id": user_id, "version": version },
+                    change_update(change, action, reapply, actor),
+                    None,
+                )
+                .await
+                .is_ok_and(|result| result.matched_count == 1),
+            Err(_) => false,
+        };
+        if !put_back {
+            stuck.push(format!("{} batch {}", change.name, change.batch_number));
+        }
+    }
+    stuck
+}
+
+// Undo a bulk operation. It is refused if any of its batches has been saved since.
+// As with applying, a batch saved while the revert runs or a ledger failure puts the
+// reverted batches back and leaves the operation applied.
+#[command]
+pub async fn revert_bulk_operation(
+    user_id: String,
+    operation_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<BulkOperation, AppError> {
+    let actor = require_privileged(&db.db, &session, &user_id).await?;
+    let operations: Collection<BulkOperation> = db.db.collection("bulk_operations");
+    let object_id = ObjectId::parse_str(&operation_id)?;
+    let operation = operations
+        .find_one(doc! { "_id": object_id, "user_id": &user_id }, None)
+        .await?
+        .ok_or_else(|| AppError::not_found("No bulk operation found for the specified user and ID."))?;
+    if operation.reverted_at.is_some() {
+        return Err(AppError::conflict("This bulk operation has already been reverted"));
+    }
+    if operation.status == "failed" {
+        return Err(AppError::conflict("This bulk operation failed and was already undone"));
+    }
+
+    let collection: Collection<Medicine> = db.db.collection("medicines");
+    let ids = operation
+        .changes
+        .iter()
+        .map(|c| ObjectId::parse_str(&c.medicine_id))
+        .collect::<Result<Vec<_>, _>>()?;
+    let current: Vec<Medicine> = collection
+        .find(doc! { "_id": { "$in": ids }, "user_id": &user_id }, None)
+        .await?
+        .try_collect()
+        .await?;
+    let changed_since: Vec<String> = operation
+        .changes
+        .iter()
+        .filter(|c| {
+            !current
+                .iter()
+                .any(|m| m.id.is_some_and(|id| id.to_hex() == c.medicine_id) && m.version == c.version_after)
+        })
+        .map(|c| format!("{} batch {}", c.name, c.batch_number))
+        .collect();
+    if !changed_since.is_empty() {
+        return Err(AppError::conflict(format!(
+            "Cannot revert: {} changed since the operation",
+            changed_since.join(", ")
+        )));
+    }
+
+    let action = &operation.action;
+    let mut reverted: Vec<Medicine> = Vec::new();
+    let mut reverted_changes: Vec<BulkChange> = Vec::new();
+    let mut skipped: Vec<String> = Vec::new();
+    let mut failure: Option<AppError> = None;
+    for change in &operation.changes {
+        let object_id = ObjectId::parse_str(&change.medicine_id)?;
+        let filter = doc! { "_id": object_id, "user_id": &user_id, "version": change.version_after };
+        match collection.find_one_and_update(filter, change_update(change, action, false, &actor), None).await {
+            Ok(Some(medicine)) => {
+                reverted.push(medicine);
+                reverted_changes.push(change.clone());
+            }
+            Ok(None) => skipped.push(format!("{} batch {}", change.name, change.batch_number)),
+            Err(e) => {
+                failure = Some(e.into());
+                break;
+            }
+        }
+    }
+    if failure.is_none() && !skipped.is_empty() {
+        failure = Some(AppError::conflict(format!(
+            "Cannot revert: {} changed while the operation was being reverted",
+            skipped.join(", ")
+        )));
+    }
+    if failure.is_none() {
+        let reference = format!("revert bulk {}", operation_id);
+        let movements = change_movements(&reverted, &reverted_changes, action, false, &reference);
+        if let Err(e) = record_movements(&db.db, movements).await {
+            failure = Some(e);
+        }
+    }
+
+    if let Some(error) = failure {
+        let stuck = rollback(&collection, &user_id, &reverted_changes, action, true, &actor).await;
+        if stuck.is_empty() {
+            return Err(error);
+        }
+        log::error!("Revert of bulk operation {} could not put back: {}", operation_id, stuck.join(", "));
+        return Err(AppError::internal(format!(
+            "{} The revert was undone except for {}, which need checking by hand.",
+            error,
+            stuck.join(", ")
+        )));
+    }
+
+    let reverted_at = Utc::now().timestamp_millis();
+    operations
+        .update_one(
+            doc! { "_id": object_id },
+            doc! { "$set": { "reverted_at": reverted_at, "reverted_by": &actor } },
+            None,
+        )
+        .await?;
+    Ok(BulkOperation { reverted_at: Some(reverted_at), reverted_by: Some(actor), ..operation })
+}
+
+// Recent bulk operations, newest first, for the history screen
+#[command]
+pub async fn get_bulk_operations(
+    user_id: String,
+    limit: u32,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<BulkOperation>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    if limit == 0 {
+        return Err(AppError::validation("limit", "Limit must be at least 1"));
+    }
+    let operations: Collection<BulkOperation> = db.db.collection("bulk_operations");
+    let options = FindOptions::builder().sort(doc! { "created_at": -1 }).limit(limit as i64).build();
+    let history = operations.find(doc! { "user_id": &user_id }, options).await?.try_collect().await?;
+    Ok(history)
+}
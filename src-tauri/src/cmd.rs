@@ -37,10 +37,16 @@
 
 use tauri::State;
 use mongodb::Collection;
-use crate::user::{signup_user, login_user};
-use crate::model::User;
+use crate::user::{create_user, signup_user, login_user, find_user, normalize_username, validate_profile, STAFF_ROLES};
+use crate::model::{ProfileUpdate, User, UserProfile};
 use crate::db::DbState; // Import your DbState struct
-use mongodb::bson::{doc, oid::ObjectId};
+use crate::error::AppError;
+use crate::password::{load_password_policy, upgrade_hash_if_needed, PasswordPolicy};
+use crate::session::{require_operator, require_role, start_session, SessionState};
+use crate::login_guard::{check_lockout, clear_failures, record_failure, record_login_event};
+use crate::tenant::bootstrap_tenant;
+use mongodb::bson::{doc, Document};
+use futures::TryStreamExt;
 
 // #[tauri::command]
 // pub async fn signup(
@@ -91,36 +97,161 @@ pub async fn signup(
     password: String,
     email: String,
     db: State<'_, DbState>,
-) -> Result<String, String> { // Return a String (user_id) on success
+    session: State<'_, SessionState>,
+) -> Result<String, AppError> { // Return a String (user_id) on success
     let user_collection: &Collection<User> = &db.db.collection("users");
 
-    let existing_user = user_collection
-        .find_one(doc! { "email": &email }, None)
-        .await
-        .map_err(|e| format!("Database error: {}", e))?;
-
-    if existing_user.is_some() {
-        return Err("Email already in use".to_string());
-    }
-
-    match signup_user(user_collection, &username, &password, &email).await {
+    // A new store starts with the default policy until its owner changes it
+    let policy = PasswordPolicy::default();
+    match signup_user(user_collection, &username, &password, &email, &policy).await {
         Ok(user) => {
+            bootstrap_tenant(&db.db, &user.store()).await?;
+            start_session(&db, &session, &user).await?;
             // Unwrap the optional `id` and convert it to a hex string
-            Ok(user.id.ok_or("Failed to retrieve user ID")?.to_hex())
+            Ok(user.id.ok_or_else(|| AppError::internal("Failed to retrieve user ID"))?.to_hex())
         },
         Err(e) => Err(e),
     }
 }
 
 #[tauri::command]
-pub async fn login(username: String, password: String, db: State<'_, DbState>) -> Result<String, String> {
-    let user_collection: &Collection<User> = &db.db.collection("users");
+pub async fn login(
+    username: String,
+    password: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<String, AppError> {
+    let username = normalize_username(&username);
+
+    if let Err(e) = check_lockout(&db.db, &username).await {
+        record_login_event(&db.db, &username, None, "locked").await?;
+        return Err(e);
+    }
 
-    match login_user(user_collection, &username, &password).await {
+    match login_user(&db.db, &username, &password).await {
         Ok(user) => {
-            // Return user ID as a hex string to be stored in the frontend
-            Ok(user.id.ok_or("Failed to retrieve user ID")?.to_hex())
+            let user_id = user.id.ok_or_else(|| AppError::internal("Failed to retrieve user ID"))?.to_hex();
+            clear_failures(&db.db, &username).await?;
+            record_login_event(&db.db, &username, Some(user_id.clone()), "success").await?;
+            // A failed re-hash leaves the old hash working, so it must not block the login
+            let _ = upgrade_hash_if_needed(&db.db, &user, &password).await;
+            // Stores created before bootstrapping existed get their defaults on the next login
+            bootstrap_tenant(&db.db, &user.store()).await?;
+            start_session(&db, &session, &user).await?;
+            // Return the store's ID as a hex string to be stored in the frontend;
+            // for an owner it is their own ID
+            Ok(user.store())
         },
-        Err(e) => Err(e),
+        Err(e) => {
+            record_failure(&db.db, &username).await?;
+            record_login_event(&db.db, &username, None, "failure").await?;
+            Err(e)
+        }
+    }
+}
+
+// Retrieve the unlocked user's own profile
+#[tauri::command]
+pub async fn get_profile(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<UserProfile, AppError> {
+    require_operator(&db.db, &session, &user_id).await.map(UserProfile::from)
+}
+
+// Update the unlocked user's display name, contact and registration details
+#[tauri::command]
+pub async fn update_profile(
+    user_id: String,
+    profile: ProfileUpdate,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<UserProfile, AppError> {
+    let user_collection: &Collection<User> = &db.db.collection("users");
+    let operator = require_operator(&db.db, &session, &user_id).await?;
+    let profile = validate_profile(profile)?;
+    let object_id = operator.id.ok_or_else(|| AppError::internal("Failed to retrieve user ID"))?;
+
+    // Only the fields that were sent change, as with patch_medicine
+    let mut set = Document::new();
+    let mut unset = Document::new();
+    let fields = [
+        ("display_name", profile.display_name),
+        ("phone", profile.phone),
+        ("qualification", profile.qualification),
+        ("registration_number", profile.registration_number),
+        ("avatar_path", profile.avatar_path),
+    ];
+    for (field, value) in fields {
+        match value {
+            Some(v) if v.is_empty() => {
+                unset.insert(field, "");
+            }
+            Some(v) => {
+                set.insert(field, v);
+            }
+            None => {}
+        }
+    }
+    let mut update = Document::new();
+    if !set.is_empty() {
+        update.insert("$set", set);
     }
+    if !unset.is_empty() {
+        update.insert("$unset", unset);
+    }
+    if update.is_empty() {
+        return Ok(UserProfile::from(operator));
+    }
+
+    let result = user_collection.update_one(doc! { "_id": object_id }, update, None).await?;
+
+    if result.matched_count == 0 {
+        return Err(AppError::not_found("No user found for the specified ID."));
+    }
+
+    find_user(user_collection, &object_id.to_hex()).await.map(UserProfile::from)
+}
+
+// Add a staff account to the store. Only the owner may do this, and staff can be
+// a manager, pharmacist or cashier but never another owner.
+#[tauri::command]
+pub async fn create_staff_user(
+    user_id: String,
+    username: String,
+    password: String,
+    email: String,
+    role: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<UserProfile, AppError> {
+    require_role(&db.db, &session, &user_id, &["owner"], "Only the owner can add staff").await?;
+    let role = role.trim().to_lowercase();
+    if !STAFF_ROLES.contains(&role.as_str()) {
+        return Err(AppError::validation("role", "Role must be manager, pharmacist or cashier"));
+    }
+
+    let user_collection: &Collection<User> = &db.db.collection("users");
+    let policy = load_password_policy(&db.db, &user_id).await?;
+    let user = create_user(user_collection, &username, &password, &email, &role, Some(user_id), &policy).await?;
+    Ok(UserProfile::from(user))
+}
+
+// Everyone who works for the store, owner first
+#[tauri::command]
+pub async fn get_staff_users(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<UserProfile>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let user_collection: &Collection<User> = &db.db.collection("users");
+    let owner = find_user(user_collection, &user_id).await?;
+    let staff: Vec<User> = user_collection
+        .find(doc! { "store_id": &user_id }, None)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(std::iter::once(owner).chain(staff).map(UserProfile::from).collect())
 }
@@ -176,19 +176,27 @@
 // }
 
 
-use crate::database::get_db_connection;
-use mongodb::bson::{doc, Document, oid::ObjectId};
+use chrono::{NaiveDate, Utc};
+use mongodb::bson::{doc, Bson, Document, oid::ObjectId};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
 use mongodb::Collection;
 use serde::{Deserialize, Serialize};
 use tauri::{command, State};
+use crate::billing::escape_regex;
 use crate::db::DbState;
+use crate::error::AppError;
+use crate::ledger::{record_movements, StockMovement};
+use crate::session::{require_operator, SessionState};
+use crate::settings::load_store_settings;
+use crate::tenant::bootstrap_tenant;
+use crate::validation::{duplicate_batch_error, validate_medicine, MedicineRules};
 use futures::stream::StreamExt;
 use futures::TryStreamExt;
 use mongodb::bson;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Medicine {
-    #[serde(rename = "_id")]
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>, // MongoDB ID compatibility
     pub name: String,
     pub batch_number: String,
@@ -199,6 +207,63 @@ pub struct Medicine {
     pub wholesaler_name: String,
     pub purchase_date: String,
     pub user_id: String, // User ID to associate medicines with specific users
+    #[serde(default)]
+    pub category: Option<String>, // e.g. "Tablet", "Syrup", "Surgical"
+    #[serde(default)]
+    pub manufacturer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub import_id: Option<String>, // Set on batches created by a file import so it can be undone
+    #[serde(default)]
+    pub version: i64, // Bumped on every write, used to reject stale patches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>, // Set when the batch is moved to the trash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<String>, // User who deleted it
+}
+
+// Fields of a batch to change; anything left out keeps its current value
+#[derive(Debug, Default, Deserialize)]
+pub struct MedicinePatch {
+    pub name: Option<String>,
+    pub batch_number: Option<String>,
+    pub expiry_date: Option<String>,
+    pub quantity: Option<u32>,
+    pub purchase_price: Option<f64>,
+    pub selling_price: Option<f64>,
+    pub wholesaler_name: Option<String>,
+    pub purchase_date: Option<String>,
+    pub category: Option<String>, // An empty string clears the category
+    pub manufacturer: Option<String>, // An empty string clears the manufacturer
+}
+
+// Columns of a batch that the medicine list can sort on and return
+const MEDICINE_COLUMNS: [&str; 12] = [
+    "name", "batch_number", "expiry_date", "quantity", "purchase_price", "selling_price",
+    "wholesaler_name", "purchase_date", "category", "manufacturer", "import_id", "version",
+];
+
+// Largest page the medicine list returns
+const MAX_PAGE_SIZE: u32 = 500;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct MedicineFilter {
+    pub name: Option<String>, // Case-insensitive match on part of the name
+    pub supplier: Option<String>, // Case-insensitive match on the wholesaler name
+    pub category: Option<String>,
+    pub expiry_from: Option<String>, // YYYY-MM-DD, both inclusive
+    pub expiry_to: Option<String>,
+    pub min_quantity: Option<u32>,
+    pub max_quantity: Option<u32>,
+    #[serde(default)]
+    pub in_stock_only: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MedicinePage {
+    pub medicines: Vec<Document>, // Only the requested fields, plus `_id`
+    pub total_count: u64,
+    pub page: u32,
+    pub limit: u32,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -207,40 +272,148 @@ pub struct MedicineInfo {
     pub selling_price: Option<f64>, // Optional in case some documents lack this field
 }
 
-// Initialize the medicines collection
+// Set up the store's settings, categories and counters if they are missing.
+// Safe to call on every login.
 #[command]
-pub async fn initialize_db(user_id: String, db: State<'_, DbState>) -> Result<String, String> {
+pub async fn initialize_db(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<String, AppError> {
     if user_id.is_empty() {
-        return Err("User ID cannot be empty".to_string());
-    }
-
-    // Example: Create a collection specific to the user (can be adjusted to your logic)
-    let user_collection_name = format!("medicines_{}", user_id);
-    let _medicine_collection: Collection<Document> = db.db.collection(&user_collection_name);
-
-    // Simulate an operation to check the collection is ready (e.g., inserting a dummy document)
-    match _medicine_collection.insert_one(doc! {"initialized": true}, None).await {
-        Ok(_) => Ok(format!("Medicines collection for user {} is ready.", user_id)),
-        Err(err) => Err(format!("Failed to initialize database for user {}: {}", user_id, err)),
+        return Err(AppError::validation("user_id", "User ID cannot be empty"));
     }
+    require_operator(&db.db, &session, &user_id).await?;
+    // Every store shares the `medicines` collection, so there is nothing per user to create there
+    bootstrap_tenant(&db.db, &user_id).await?;
+    Ok(format!("Store for user {} is ready.", user_id))
 }
 
-// Retrieve all medicines for a specific user
+// Retrieve all medicines for a specific user. Screens that show the stock list
+// should use `list_medicines`, which pages on the server.
 #[command]
-pub async fn get_medicine(user_id: String, db: State<'_, DbState>) -> Result<Vec<Medicine>, String> {
+pub async fn get_medicine(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<Medicine>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
     let collection: Collection<Medicine> = db.db.collection("medicines");
 
-    // Filter medicines by `user_id` to fetch only user-specific data
-    let filter = doc! { "user_id": &user_id };
-    let cursor = collection.find(filter, None).await.map_err(|e| e.to_string())?;
-    let medicines: Vec<Medicine> = cursor.try_collect().await.map_err(|e| e.to_string())?;
+    // Filter medicines by `user_id` to fetch only user-specific data; trashed batches are left out
+    let filter = doc! { "user_id": &user_id, "deleted_at": null };
+    let cursor = collection.find(filter, None).await?;
+    let medicines: Vec<Medicine> = cursor.try_collect().await?;
 
     Ok(medicines)
 }
 
 
+// List a user's medicines a page at a time, with filters, sorting and an optional
+// set of fields to return
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub async fn list_medicines(
+    user_id: String,
+    filter: Option<MedicineFilter>,
+    page: u32,
+    limit: u32,
+    sort_by: Option<String>,
+    descending: Option<bool>,
+    fields: Option<Vec<String>>,
+    db: State<'_, DbState>,
+) -> Result<MedicinePage, AppError> {
+    if page == 0 || limit == 0 {
+        return Err(AppError::validation("page", "Page and limit must be at least 1"));
+    }
+    if limit > MAX_PAGE_SIZE {
+        return Err(AppError::validation("limit", format!("Limit must be at most {}", MAX_PAGE_SIZE)));
+    }
+    let filter = filter.unwrap_or_default();
+
+    let mut query = doc! { "user_id": &user_id, "deleted_at": null };
+    let text = |value: &Option<String>| value.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(String::from);
+    if let Some(name) = text(&filter.name) {
+        query.insert("name", doc! { "$regex": escape_regex(&name), "$options": "i" });
+    }
+    if let Some(supplier) = text(&filter.supplier) {
+        query.insert("wholesaler_name", doc! { "$regex": escape_regex(&supplier), "$options": "i" });
+    }
+    if let Some(category) = text(&filter.category) {
+        query.insert("category", category);
+    }
+    let mut expiry = Document::new();
+    for (field, operator, value) in [
+        ("expiry_from", "$gte", text(&filter.expiry_from)),
+        ("expiry_to", "$lte", text(&filter.expiry_to)),
+    ] {
+        if let Some(date) = value {
+            if NaiveDate::parse_from_str(&date, "%Y-%m-%d").is_err() {
+                return Err(AppError::validation(field, format!("'{}' is not a date in YYYY-MM-DD format", date)));
+            }
+            expiry.insert(operator, date);
+        }
+    }
+    if !expiry.is_empty() {
+        query.insert("expiry_date", expiry);
+    }
+    let mut quantity = Document::new();
+    if let Some(min) = filter.min_quantity {
+        quantity.insert("$gte", min as i64);
+    }
+    if filter.in_stock_only && filter.min_quantity.unwrap_or(0) == 0 {
+        quantity.insert("$gt", 0_i64);
+    }
+    if let Some(max) = filter.max_quantity {
+        quantity.insert("$lte", max as i64);
+    }
+    if !quantity.is_empty() {
+        query.insert("quantity", quantity);
+    }
+
+    let sort_field = sort_by.unwrap_or_else(|| "name".to_string());
+    if !MEDICINE_COLUMNS.contains(&sort_field.as_str()) {
+        return Err(AppError::validation("sort_by", format!("Cannot sort medicines by '{}'", sort_field)));
+    }
+    let direction = if descending.unwrap_or(false) { -1 } else { 1 };
+    let projection = match fields {
+        Some(fields) if !fields.is_empty() => {
+            let mut projection = Document::new();
+            for field in fields {
+                if !MEDICINE_COLUMNS.contains(&field.as_str()) {
+                    return Err(AppError::validation("fields", format!("Unknown medicine field '{}'", field)));
+                }
+                projection.insert(field, 1);
+            }
+            Some(projection)
+        }
+        _ => None,
+    };
+
+    let collection: Collection<Document> = db.db.collection("medicines");
+    let total_count = collection.count_documents(query.clone(), None).await?;
+
+    // `_id` breaks ties so rows do not shuffle between pages
+    let options = FindOptions::builder()
+        .sort(doc! { sort_field: direction, "_id": direction })
+        .projection(projection)
+        .skip(((page - 1) as u64) * limit as u64)
+        .limit(limit as i64)
+        .build();
+    let medicines: Vec<Document> = collection.find(query, options).await?.try_collect().await?;
+
+    Ok(MedicinePage {
+        medicines,
+        total_count,
+        page,
+        limit,
+    })
+}
+
+
 // Insert a new medicine for a specific user
 #[command]
+#[allow(clippy::too_many_arguments)]
 pub async fn insert_medicine(
     user_id: String,
     name: String,
@@ -251,34 +424,55 @@ pub async fn insert_medicine(
     selling_price: f64,
     wholesaler_name: String,
     purchase_date: String,
-    db: State<'_, DbState>, // Add `db` state here
-) -> Result<String, String> {
+    category: Option<String>,
+    manufacturer: Option<String>,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<String, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
     let collection: Collection<Medicine> = db.db.collection("medicines");
 
-    let new_medicine = Medicine {
+    let mut new_medicine = Medicine {
         id: None,
-        name,
-        batch_number,
-        expiry_date,
+        name: name.trim().to_string(),
+        batch_number: batch_number.trim().to_string(),
+        expiry_date: expiry_date.trim().to_string(),
         quantity,
         purchase_price,
         selling_price,
-        wholesaler_name,
-        purchase_date,
+        wholesaler_name: wholesaler_name.trim().to_string(),
+        purchase_date: purchase_date.trim().to_string(),
         user_id, // Add `user_id` when inserting a new medicine
+        category,
+        manufacturer: cleaned(manufacturer),
+        import_id: None,
+        version: 0,
+        deleted_at: None,
+        deleted_by: None,
     };
+    let settings = load_store_settings(&db.db, &new_medicine.user_id).await?;
+    let warnings = validate_medicine(&new_medicine, &MedicineRules::from(&settings))?;
 
-    collection
-        .insert_one(new_medicine, None)
+    // The store/product/batch unique index rejects duplicate batches
+    let result = collection
+        .insert_one(&new_medicine, None)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| duplicate_batch_error(e, &new_medicine))?;
+
+    new_medicine.id = result.inserted_id.as_object_id();
+    let receipt = StockMovement::for_batch(&new_medicine, new_medicine.quantity as i64, "receipt", None);
+    // The batch is saved either way; a missing ledger entry is corrected by hand
+    if let Err(e) = record_movements(&db.db, vec![receipt]).await {
+        log::error!("Batch {} was added but its receipt was not recorded: {}", new_medicine.batch_number, e);
+    }
 
-    Ok("Medicine inserted successfully.".to_string())
+    Ok(with_warnings("Medicine inserted successfully.", warnings))
 }
 
 
 // Update a specific medicine for a specific user
 #[command]
+#[allow(clippy::too_many_arguments)]
 pub async fn update_medicine(
     user_id: String,
     id: String,
@@ -290,55 +484,231 @@ pub async fn update_medicine(
     selling_price: f64,
     wholesaler_name: String,
     purchase_date: String,
+    category: Option<String>,
+    manufacturer: Option<String>,
     db: State<'_, DbState>,
-) -> Result<String, String> {
+    session: State<'_, SessionState>,
+) -> Result<String, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
     let collection: Collection<Medicine> = db.db.collection("medicines");
 
     // Filter by `user_id` and `id` to ensure user-specific update
-    let object_id = ObjectId::parse_str(&id).map_err(|e| e.to_string())?;
-    let filter = doc! { "_id": object_id, "user_id": &user_id };
-
-    let update = doc! {
-        "$set": {
-            "name": name,
-            "batch_number": batch_number,
-            "expiry_date": expiry_date,
-            "quantity": quantity,
-            "purchase_price": purchase_price,
-            "selling_price": selling_price,
-            "wholesaler_name": wholesaler_name,
-            "purchase_date": purchase_date
-        }
+    let object_id = ObjectId::parse_str(&id)?;
+    let filter = doc! { "_id": object_id, "user_id": &user_id, "deleted_at": null };
+
+    let updated = Medicine {
+        id: Some(object_id),
+        name: name.trim().to_string(),
+        batch_number: batch_number.trim().to_string(),
+        expiry_date: expiry_date.trim().to_string(),
+        quantity,
+        purchase_price,
+        selling_price,
+        wholesaler_name: wholesaler_name.trim().to_string(),
+        purchase_date: purchase_date.trim().to_string(),
+        user_id: user_id.clone(),
+        category: category.clone(),
+        manufacturer: cleaned(manufacturer.clone()),
+        import_id: None,
+        version: 0,
+        deleted_at: None,
+        deleted_by: None,
+    };
+    let settings = load_store_settings(&db.db, &user_id).await?;
+    let warnings = validate_medicine(&updated, &MedicineRules::from(&settings))?;
+    let mut set = doc! {
+        "name": &updated.name,
+        "batch_number": &updated.batch_number,
+        "expiry_date": &updated.expiry_date,
+        "quantity": updated.quantity,
+        "purchase_price": updated.purchase_price,
+        "selling_price": updated.selling_price,
+        "wholesaler_name": &updated.wholesaler_name,
+        "purchase_date": &updated.purchase_date,
     };
+    // Optional fields the caller leaves out keep their stored value; an empty one clears it
+    let mut unset = Document::new();
+    for (field, value) in [("category", category), ("manufacturer", manufacturer)] {
+        match value.map(|v| v.trim().to_string()) {
+            Some(v) if v.is_empty() => {
+                unset.insert(field, "");
+            }
+            Some(v) => {
+                set.insert(field, v);
+            }
+            None => {}
+        }
+    }
+    let mut update = doc! { "$set": set, "$inc": { "version": 1_i64 } };
+    if !unset.is_empty() {
+        update.insert("$unset", unset);
+    }
 
-    let result = collection
-        .update_one(filter, update, None)
+    // The document as it was before the update tells us how much the quantity changed
+    let previous = collection
+        .find_one_and_update(filter, update, None)
+        .await
+        .map_err(|e| duplicate_batch_error(e, &updated))?
+        .ok_or_else(|| AppError::not_found("No medicine found for the specified user and ID."))?;
+
+    let change = updated.quantity as i64 - previous.quantity as i64;
+    if let Err(e) = record_movements(&db.db, vec![StockMovement::for_batch(&updated, change, "adjustment", None)]).await {
+        log::error!("Batch {} was updated but its adjustment was not recorded: {}", updated.batch_number, e);
+    }
+
+    Ok(with_warnings("Medicine updated successfully.", warnings))
+}
+
+
+// Change only the given fields of a batch. `expected_version` is the version the
+// caller last read; if someone has saved the batch since, the patch is rejected.
+#[command]
+pub async fn patch_medicine(
+    user_id: String,
+    id: String,
+    expected_version: i64,
+    patch: MedicinePatch,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Medicine, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let collection: Collection<Medicine> = db.db.collection("medicines");
+    let object_id = ObjectId::parse_str(&id)?;
+    let stale = || AppError::conflict("This medicine was changed by someone else. Reload it and try again.");
+
+    let current = collection
+        .find_one(doc! { "_id": object_id, "user_id": &user_id, "deleted_at": null }, None)
+        .await?
+        .ok_or_else(|| AppError::not_found("No medicine found for the specified user and ID."))?;
+    if current.version != expected_version {
+        return Err(stale());
+    }
+
+    let trimmed = |value: Option<String>| value.map(|v| v.trim().to_string());
+    let mut set = Document::new();
+    let mut patched = current.clone();
+    if let Some(name) = trimmed(patch.name) {
+        set.insert("name", &name);
+        patched.name = name;
+    }
+    if let Some(batch_number) = trimmed(patch.batch_number) {
+        set.insert("batch_number", &batch_number);
+        patched.batch_number = batch_number;
+    }
+    if let Some(expiry_date) = trimmed(patch.expiry_date) {
+        set.insert("expiry_date", &expiry_date);
+        patched.expiry_date = expiry_date;
+    }
+    if let Some(quantity) = patch.quantity {
+        set.insert("quantity", quantity as i64);
+        patched.quantity = quantity;
+    }
+    if let Some(purchase_price) = patch.purchase_price {
+        set.insert("purchase_price", purchase_price);
+        patched.purchase_price = purchase_price;
+    }
+    if let Some(selling_price) = patch.selling_price {
+        set.insert("selling_price", selling_price);
+        patched.selling_price = selling_price;
+    }
+    if let Some(wholesaler_name) = trimmed(patch.wholesaler_name) {
+        set.insert("wholesaler_name", &wholesaler_name);
+        patched.wholesaler_name = wholesaler_name;
+    }
+    if let Some(purchase_date) = trimmed(patch.purchase_date) {
+        set.insert("purchase_date", &purchase_date);
+        patched.purchase_date = purchase_date;
+    }
+    if let Some(category) = trimmed(patch.category) {
+        let category = Some(category).filter(|c| !c.is_empty());
+        set.insert("category", &category);
+        patched.category = category;
+    }
+    if let Some(manufacturer) = trimmed(patch.manufacturer) {
+        let manufacturer = Some(manufacturer).filter(|m| !m.is_empty());
+        set.insert("manufacturer", &manufacturer);
+        patched.manufacturer = manufacturer;
+    }
+    if set.is_empty() {
+        return Ok(current);
+    }
+
+    let settings = load_store_settings(&db.db, &user_id).await?;
+    validate_medicine(&patched, &MedicineRules::from(&settings))?;
+
+    // The version in the filter makes the check and the write one atomic step
+    let filter = doc! { "_id": object_id, "user_id": &user_id, "deleted_at": null, "version": version_filter(expected_version) };
+    let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+    let saved = collection
+        .find_one_and_update(filter, doc! { "$set": set, "$inc": { "version": 1_i64 } }, options)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| duplicate_batch_error(e, &patched))?
+        .ok_or_else(stale)?;
+
+    let change = saved.quantity as i64 - current.quantity as i64;
+    if let Err(e) = record_movements(&db.db, vec![StockMovement::for_batch(&saved, change, "adjustment", None)]).await {
+        log::error!("Batch {} was saved but its adjustment was not recorded: {}", saved.batch_number, e);
+    }
 
-    if result.matched_count == 0 {
-        return Err("No medicine found for the specified user and ID.".to_string());
+    Ok(saved)
+}
+
+// Match a batch only while it is still at `version`. Batches saved before
+// versioning have no `version` field, which `null` matches.
+pub fn version_filter(version: i64) -> Bson {
+    if version == 0 {
+        Bson::Document(doc! { "$in": [0_i64, Bson::Null] })
+    } else {
+        Bson::Int64(version)
     }
+}
 
-    Ok("Medicine updated successfully.".to_string())
+fn cleaned(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+// Append validation warnings to a success message
+fn with_warnings(message: &str, warnings: Vec<String>) -> String {
+    if warnings.is_empty() {
+        message.to_string()
+    } else {
+        format!("{} Warning: {}.", message, warnings.join("; "))
+    }
 }
 
 
-// Delete a specific medicine for a specific user
+// Move a specific medicine to the trash for a specific user. It can be restored
+// until the store's retention period runs out, after which it is purged.
 #[command]
-pub async fn delete_medicine(user_id: String, id: String, db: State<'_, DbState>) -> Result<String, String> {
+pub async fn delete_medicine(
+    user_id: String,
+    id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<String, AppError> {
+    let operator = require_operator(&db.db, &session, &user_id).await?;
     let collection: Collection<Medicine> = db.db.collection("medicines");
 
-    let object_id = ObjectId::parse_str(&id).map_err(|e| e.to_string())?;
-    let filter = doc! { "_id": object_id, "user_id": &user_id };
+    let object_id = ObjectId::parse_str(&id)?;
+    let filter = doc! { "_id": object_id, "user_id": &user_id, "deleted_at": null };
+    // Attribute the delete to whoever is at the terminal
+    let actor = operator.id.map(|id| id.to_hex()).unwrap_or_default();
+    let update = doc! {
+        "$set": { "deleted_at": Utc::now().timestamp_millis(), "deleted_by": &actor },
+        "$inc": { "version": 1_i64 }
+    };
 
-    let result = collection.delete_one(filter, None).await.map_err(|e| e.to_string())?;
+    let deleted = collection
+        .find_one_and_update(filter, update, None)
+        .await?
+        .ok_or_else(|| AppError::not_found("No medicine found for the specified user and ID."))?;
 
-    if result.deleted_count == 0 {
-        return Err("No medicine found for the specified user and ID.".to_string());
+    let removal = StockMovement::for_batch(&deleted, -(deleted.quantity as i64), "delete", None);
+    if let Err(e) = record_movements(&db.db, vec![removal]).await {
+        log::error!("Batch {} was deleted but its removal was not recorded: {}", deleted.batch_number, e);
     }
 
-    Ok("Medicine deleted successfully.".to_string())
+    Ok("Medicine moved to the trash.".to_string())
 }
 
 
@@ -350,13 +720,19 @@ pub async fn search_medicines(
     page: u32,
     limit: u32,
     db: State<'_, DbState>,
-) -> Result<Vec<MedicineInfo>, String> {
+    session: State<'_, SessionState>,
+) -> Result<Vec<MedicineInfo>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    if page == 0 || limit == 0 {
+        return Err(AppError::validation("page", "Page and limit must be at least 1"));
+    }
     let medicine_collection: Collection<Document> = db.db.collection("medicines");
     let skip = (page - 1) * limit;
 
     // Add user_id filter to search results for user-specific data
     let filter = doc! {
         "user_id": &user_id,
+        "deleted_at": null,
         "name": { "$regex": &query, "$options": "i" }
     };
     let options = mongodb::options::FindOptions::builder()
@@ -364,24 +740,23 @@ pub async fn search_medicines(
         .skip(skip as u64)
         .build();
 
-    let mut cursor = medicine_collection.find(filter, options)
-        .await
-        .map_err(|e| format!("Database error: {}", e))?;
+    let mut cursor = medicine_collection.find(filter, options).await?;
 
     let mut medicines: Vec<MedicineInfo> = Vec::new();
 
     while let Some(result) = cursor.next().await {
         match result {
             Ok(doc) => {
-                match bson::from_document::<MedicineInfo>(doc.clone()) {
+                let id = doc.get_object_id("_id").map(|id| id.to_hex()).unwrap_or_default();
+                match bson::from_document::<MedicineInfo>(doc) {
                     Ok(medicine) => medicines.push(medicine),
-                    Err(_) => {
-                        println!("Failed to deserialize document: {:?}", doc);
-                        return Err("Failed to deserialize medicine document.".to_string());
+                    Err(e) => {
+                        log::warn!("Failed to deserialize medicine {}: {}", id, e);
+                        return Err(AppError::internal("Failed to deserialize medicine document."));
                     }
                 }
             }
-            Err(e) => return Err(format!("Error retrieving medicine: {}", e)),
+            Err(e) => return Err(e.into()),
         }
     }
 
@@ -0,0 +1,523 @@
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use mongodb::options::FindOptions;
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::billing::{format_local_date, local_day_bounds, round2, PaymentMode};
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::session::{require_operator, require_role, SessionState};
+use crate::user::PRIVILEGED_ROLES;
+
+const DAY_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+// Times a payment re-reads the unpaid charges after losing a race for one of them
+const ALLOCATION_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Customer {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub name: String,
+    pub phone: String,
+    pub address: String,
+    pub credit_limit: f64,
+    pub balance: f64, // Amount currently owed by the customer
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionKind {
+    Charge,  // Bill posted on account
+    Payment, // Money received against the balance
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomerTransaction {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub customer_id: String,
+    pub kind: TransactionKind,
+    pub amount: f64,
+    pub outstanding: f64, // Unpaid part of a charge, always zero for payments
+    pub bill_number: Option<String>,
+    pub mode: Option<PaymentMode>,
+    pub reference: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgeingReport {
+    pub customer_id: String,
+    pub customer_name: String,
+    pub current: f64,        // 0-30 days
+    pub days_31_to_60: f64,
+    pub over_60: f64,
+    pub total: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatementLine {
+    pub date: String,
+    pub description: String,
+    pub debit: f64,
+    pub credit: f64,
+    pub balance: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CustomerStatement {
+    pub customer: Customer,
+    pub from: String,
+    pub to: String,
+    pub opening_balance: f64,
+    pub lines: Vec<StatementLine>,
+    pub closing_balance: f64,
+}
+
+pub async fn find_customer(db: &Database, user_id: &str, customer_id: &str) -> Result<Customer, AppError> {
+    let collection: Collection<Customer> = db.collection("customers");
+    let object_id = ObjectId::parse_str(customer_id)?;
+    collection
+        .find_one(doc! { "_id": object_id, "user_id": user_id }, None)
+        .await?
+        .ok_or_else(|| AppError::not_found("No customer found for the specified user and ID."))
+}
+
+// Raise the customer's balance by `amount`, refusing when it would pass the credit limit
+pub async fn reserve_credit(
+    db: &Database,
+    user_id: &str,
+    customer_id: &str,
+    amount: f64,
+    allow_over_limit: bool,
+) -> Result<(), AppError> {
+    let collection: Collection<Customer> = db.collection("customers");
+    let object_id = ObjectId::parse_str(customer_id)?;
+
+    let mut filter = doc! { "_id": object_id, "user_id": user_id };
+    if !allow_over_limit {
+        // Checked in the same update so two counters cannot both squeeze under the limit
+        filter.insert(
+            "$expr",
+            doc! { "$lte": [ { "$add": ["$balance", amount] }, "$credit_limit" ] },
+        );
+    }
+
+    let result = collection
+        .update_one(filter, doc! { "$inc": { "balance": amount } }, None)
+        .await?;
+
+    if result.matched_count == 0 {
+        let customer = find_customer(db, user_id, customer_id).await?;
+        return Err(AppError::conflict(format!(
+            "Credit limit exceeded for {}: balance {:.2}, limit {:.2}, bill {:.2}",
+            customer.name, customer.balance, customer.credit_limit, amount
+        )));
+    }
+    Ok(())
+}
+
+// Undo a reservation when the bill it was made for could not be saved
+pub async fn release_credit(db: &Database, user_id: &str, customer_id: &str, amount: f64) {
+    let collection: Collection<Customer> = db.collection("customers");
+    if let Ok(object_id) = ObjectId::parse_str(customer_id) {
+        let _ = collection
+            .update_one(
+                doc! { "_id": object_id, "user_id": user_id },
+                doc! { "$inc": { "balance": -amount } },
+                None,
+            )
+            .await;
+    }
+}
+
+// Record a bill posted on account in the customer's ledger
+pub async fn post_charge(
+    db: &Database,
+    user_id: &str,
+    customer_id: &str,
+    bill_number: &str,
+    amount: f64,
+    created_at: i64,
+) -> Result<(), AppError> {
+    let transactions: Collection<CustomerTransaction> = db.collection("customer_transactions");
+    let charge = CustomerTransaction {
+        id: None,
+        user_id: user_id.to_string(),
+        customer_id: customer_id.to_string(),
+        kind: TransactionKind::Charge,
+        amount,
+        outstanding: amount,
+        bill_number: Some(bill_number.to_string()),
+        mode: None,
+        reference: None,
+        created_at,
+    };
+    transactions.insert_one(charge, None).await?;
+    Ok(())
+}
+
+async fn open_charges(db: &Database, user_id: &str, customer_id: &str) -> Result<Vec<CustomerTransaction>, AppError> {
+    let transactions: Collection<CustomerTransaction> = db.collection("customer_transactions");
+    let filter = doc! {
+        "user_id": user_id,
+        "customer_id": customer_id,
+        "kind": "charge",
+        "outstanding": { "$gt": 0.0 }
+    };
+    let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+    Ok(transactions.find(filter, options).await?.try_collect().await?)
+}
+
+// Create a customer account that can buy on credit
+#[command]
+pub async fn create_customer(
+    user_id: String,
+    name: String,
+    phone: String,
+    address: String,
+    credit_limit: f64,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Customer, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    if name.trim().is_empty() {
+        return Err(AppError::validation("name", "Customer name cannot be empty"));
+    }
+    if !credit_limit.is_finite() || credit_limit < 0.0 {
+        return Err(AppError::validation("credit_limit", "Credit limit must be a non-negative amount"));
+    }
+
+    let collection: Collection<Customer> = db.db.collection("customers");
+    let mut customer = Customer {
+        id: None,
+        user_id,
+        name: name.trim().to_string(),
+        phone: phone.trim().to_string(),
+        address: address.trim().to_string(),
+        credit_limit: round2(credit_limit),
+        balance: 0.0,
+        created_at: DateTime::now().timestamp_millis(),
+    };
+
+    let result = collection
+        .insert_one(&customer, None)
+        .await?;
+    customer.id = result.inserted_id.as_object_id();
+
+    Ok(customer)
+}
+
+async fn load_customers(db: &Database, user_id: &str) -> Result<Vec<Customer>, AppError> {
+    let collection: Collection<Customer> = db.collection("customers");
+    let options = FindOptions::builder().sort(doc! { "name": 1 }).build();
+    Ok(collection.find(doc! { "user_id": user_id }, options).await?.try_collect().await?)
+}
+
+// Retrieve all customer accounts for a specific user
+#[command]
+pub async fn get_customers(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<Customer>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    load_customers(&db.db, &user_id).await
+}
+
+// Change a customer's credit limit
+#[command]
+pub async fn update_credit_limit(
+    user_id: String,
+    customer_id: String,
+    credit_limit: f64,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<String, AppError> {
+    let denied = "Only an owner or manager can change a credit limit";
+    require_role(&db.db, &session, &user_id, &PRIVILEGED_ROLES, denied).await?;
+    if !credit_limit.is_finite() || credit_limit < 0.0 {
+        return Err(AppError::validation("credit_limit", "Credit limit must be a non-negative amount"));
+    }
+
+    let collection: Collection<Customer> = db.db.collection("customers");
+    let object_id = ObjectId::parse_str(&customer_id)?;
+    let result = collection
+        .update_one(
+            doc! { "_id": object_id, "user_id": &user_id },
+            doc! { "$set": { "credit_limit": round2(credit_limit) } },
+            None,
+        )
+        .await?;
+
+    if result.matched_count == 0 {
+        return Err(AppError::not_found("No customer found for the specified user and ID."));
+    }
+
+    Ok("Credit limit updated successfully.".to_string())
+}
+
+// Receive money against a customer's outstanding balance, settling the oldest bills first
+#[command]
+pub async fn receive_customer_payment(
+    user_id: String,
+    customer_id: String,
+    amount: f64,
+    mode: PaymentMode,
+    reference: Option<String>,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Customer, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    if !amount.is_finite() || amount <= 0.0 {
+        return Err(AppError::validation("amount", "Payment amount must be greater than zero"));
+    }
+    if mode == PaymentMode::Credit {
+        return Err(AppError::validation("mode", "An account cannot be settled with credit"));
+    }
+    let amount = round2(amount);
+
+    let customers: Collection<Customer> = db.db.collection("customers");
+    let object_id = ObjectId::parse_str(&customer_id)?;
+
+    // Only accept the payment if it does not take the balance below zero
+    let result = customers
+        .update_one(
+            doc! { "_id": object_id, "user_id": &user_id, "balance": { "$gte": amount } },
+            doc! { "$inc": { "balance": -amount } },
+            None,
+        )
+        .await?;
+    if result.matched_count == 0 {
+        let customer = find_customer(&db.db, &user_id, &customer_id).await?;
+        return Err(AppError::conflict(format!(
+            "Payment of {:.2} exceeds the outstanding balance of {:.2}",
+            amount, customer.balance
+        )));
+    }
+
+    // The balance only moves by what the charges move by, so a payment that cannot be
+    // spread over the unpaid bills in full is undone
+    let mut allocated = Vec::new();
+    let unallocated = match allocate_payment(&db.db, &user_id, &customer_id, amount, &mut allocated).await {
+        Ok(unallocated) => unallocated,
+        Err(e) => {
+            undo_allocation(&db.db, &user_id, object_id, amount, &allocated).await;
+            return Err(e);
+        }
+    };
+    if unallocated > 0.0 {
+        undo_allocation(&db.db, &user_id, object_id, amount, &allocated).await;
+        return Err(AppError::conflict(format!(
+            "Payment of {:.2} is more than the unpaid bills on the account by {:.2}",
+            amount, unallocated
+        )));
+    }
+
+    let transactions: Collection<CustomerTransaction> = db.db.collection("customer_transactions");
+    let payment = CustomerTransaction {
+        id: None,
+        user_id: user_id.clone(),
+        customer_id: customer_id.clone(),
+        kind: TransactionKind::Payment,
+        amount,
+        outstanding: 0.0,
+        bill_number: None,
+        mode: Some(mode),
+        reference,
+        created_at: DateTime::now().timestamp_millis(),
+    };
+    if let Err(e) = transactions.insert_one(payment, None).await {
+        undo_allocation(&db.db, &user_id, object_id, amount, &allocated).await;
+        return Err(e.into());
+    }
+
+    find_customer(&db.db, &user_id, &customer_id).await
+}
+
+// Settle the oldest unpaid charges with `amount`, noting each charge settled in `allocated`.
+// Each charge is only updated if nobody else has settled part of it since it was read;
+// one that was is read again. Returns what could not be allocated.
+async fn allocate_payment(
+    db: &Database,
+    user_id: &str,
+    customer_id: &str,
+    amount: f64,
+    allocated: &mut Vec<(Option<ObjectId>, f64)>,
+) -> Result<f64, AppError> {
+    let transactions: Collection<CustomerTransaction> = db.collection("customer_transactions");
+    let mut remaining = amount;
+    for _ in 0..ALLOCATION_ATTEMPTS {
+        let mut stale = false;
+        for charge in open_charges(db, user_id, customer_id).await? {
+            if remaining <= 0.0 {
+                break;
+            }
+            let applied = remaining.min(charge.outstanding);
+            let result = transactions
+                .update_one(
+                    doc! { "_id": charge.id, "outstanding": charge.outstanding },
+                    doc! { "$set": { "outstanding": round2(charge.outstanding - applied) } },
+                    None,
+                )
+                .await?;
+            if result.matched_count == 0 {
+                stale = true;
+                continue;
+            }
+            allocated.push((charge.id, applied));
+            remaining = round2(remaining - applied);
+        }
+        if remaining <= 0.0 || !stale {
+            break;
+        }
+    }
+    Ok(remaining)
+}
+
+// Give back what a failed payment took off the charges and the balance
+async fn undo_allocation(
+    db: &Database,
+    user_id: &str,
+    customer_id: ObjectId,
+    amount: f64,
+    allocated: &[(Option<ObjectId>, f64)],
+) {
+    let transactions: Collection<CustomerTransaction> = db.collection("customer_transactions");
+    for (charge_id, applied) in allocated {
+        if let Err(e) = transactions
+            .update_one(doc! { "_id": charge_id }, doc! { "$inc": { "outstanding": applied } }, None)
+            .await
+        {
+            log::error!("Failed to give back {:.2} to charge {:?}: {}", applied, charge_id, e);
+        }
+    }
+    let customers: Collection<Customer> = db.collection("customers");
+    if let Err(e) = customers
+        .update_one(
+            doc! { "_id": customer_id, "user_id": user_id },
+            doc! { "$inc": { "balance": amount } },
+            None,
+        )
+        .await
+    {
+        log::error!("Failed to give back {:.2} to the balance of customer {}: {}", amount, customer_id.to_hex(), e);
+    }
+}
+
+// Split each customer's unpaid bills into 0-30, 31-60 and 60+ day buckets
+#[command]
+pub async fn get_receivables_ageing(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<AgeingReport>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let now = DateTime::now().timestamp_millis();
+    let mut reports = Vec::new();
+
+    for customer in load_customers(&db.db, &user_id).await? {
+        if customer.balance <= 0.0 {
+            continue;
+        }
+        let customer_id = customer.id.map(|id| id.to_hex()).unwrap_or_default();
+        let mut report = AgeingReport {
+            customer_id: customer_id.clone(),
+            customer_name: customer.name,
+            current: 0.0,
+            days_31_to_60: 0.0,
+            over_60: 0.0,
+            total: 0.0,
+        };
+
+        for charge in open_charges(&db.db, &user_id, &customer_id).await? {
+            let age_days = (now - charge.created_at) / DAY_MILLIS;
+            match age_days {
+                0..=30 => report.current += charge.outstanding,
+                31..=60 => report.days_31_to_60 += charge.outstanding,
+                _ => report.over_60 += charge.outstanding,
+            }
+        }
+
+        report.current = round2(report.current);
+        report.days_31_to_60 = round2(report.days_31_to_60);
+        report.over_60 = round2(report.over_60);
+        report.total = round2(report.current + report.days_31_to_60 + report.over_60);
+        reports.push(report);
+    }
+
+    Ok(reports)
+}
+
+// Build an account statement for one customer between two dates (inclusive)
+#[command]
+pub async fn get_customer_statement(
+    user_id: String,
+    customer_id: String,
+    from: String,
+    to: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<CustomerStatement, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let customer = find_customer(&db.db, &user_id, &customer_id).await?;
+    let (start, _) = local_day_bounds(&from)?;
+    let (_, end) = local_day_bounds(&to)?;
+
+    let transactions: Collection<CustomerTransaction> = db.db.collection("customer_transactions");
+    let filter = doc! {
+        "user_id": &user_id,
+        "customer_id": &customer_id,
+        "created_at": { "$lt": end }
+    };
+    let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+    let history: Vec<CustomerTransaction> = transactions
+        .find(filter, options)
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut balance = 0.0;
+    let mut opening_balance = 0.0;
+    let mut lines = Vec::new();
+    for entry in history {
+        let (debit, credit) = match entry.kind {
+            TransactionKind::Charge => (entry.amount, 0.0),
+            TransactionKind::Payment => (0.0, entry.amount),
+        };
+        balance = round2(balance + debit - credit);
+
+        if entry.created_at < start {
+            opening_balance = balance;
+            continue;
+        }
+        let description = match entry.kind {
+            TransactionKind::Charge => format!("Bill {}", entry.bill_number.unwrap_or_default()),
+            TransactionKind::Payment => match entry.reference {
+                Some(reference) => format!("Payment received ({})", reference),
+                None => "Payment received".to_string(),
+            },
+        };
+        lines.push(StatementLine {
+            date: format_local_date(entry.created_at),
+            description,
+            debit,
+            credit,
+            balance,
+        });
+    }
+
+    Ok(CustomerStatement {
+        customer,
+        from,
+        to,
+        opening_balance,
+        lines,
+        closing_balance: balance,
+    })
+}
@@ -1,28 +1,190 @@
 // src-tauri/src/db.rs
+use mongodb::options::{ClientOptions, Tls, TlsOptions};
 use mongodb::{Client, Database};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use dotenv::dotenv;
 use std::env;
 
+// Config file read from the app config dir when CATON_DB_CONFIG does not name another one
+const DEFAULT_CONFIG_FILE: &str = "database.json";
 
+// The `identifier` in tauri.conf.json, which names Tauri's app config dir
+const APP_IDENTIFIER: &str = "com.caton.app";
+
+// Database used when the config does not name one. Existing installs keep their data
+// in users_db, so prod stays on it; dev and test never share it by accident.
+fn default_database(environment: &str) -> &'static str {
+    match environment {
+        "dev" => "caton_dev",
+        "test" => "caton_test",
+        _ => "users_db",
+    }
+}
+
+// Same place as Tauri's `app_config_dir`, e.g. ~/.config/com.caton.app on Linux.
+// The database is opened before the app starts, so it cannot ask Tauri for it.
+fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_IDENTIFIER).join(DEFAULT_CONFIG_FILE))
+}
+
+// Connection settings. Every field is optional so a config file, an environment
+// section inside it and environment variables can each fill in part of it.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DbConfig {
+    pub uri: Option<String>,
+    pub database: Option<String>,
+    pub min_pool_size: Option<u32>,
+    pub max_pool_size: Option<u32>,
+    pub connect_timeout_secs: Option<u64>,
+    pub server_selection_timeout_secs: Option<u64>,
+    pub tls: Option<bool>,
+    pub tls_ca_file: Option<PathBuf>,
+    pub tls_cert_key_file: Option<PathBuf>, // Client certificate and key in one PEM file
+    pub tls_allow_invalid_certificates: Option<bool>,
+}
+
+// Layout of the config file: shared settings plus per-environment overrides, e.g.
+// `{ "uri": "...", "environments": { "test": { "database": "caton_test" } } }`
+#[derive(Debug, Default, Deserialize)]
+struct DbConfigFile {
+    #[serde(flatten)]
+    shared: DbConfig,
+    #[serde(default)]
+    environments: HashMap<String, DbConfig>,
+}
+
+impl DbConfig {
+    // Fill the fields `self` leaves empty from `fallback`
+    fn or(self, fallback: DbConfig) -> DbConfig {
+        DbConfig {
+            uri: self.uri.or(fallback.uri),
+            database: self.database.or(fallback.database),
+            min_pool_size: self.min_pool_size.or(fallback.min_pool_size),
+            max_pool_size: self.max_pool_size.or(fallback.max_pool_size),
+            connect_timeout_secs: self.connect_timeout_secs.or(fallback.connect_timeout_secs),
+            server_selection_timeout_secs: self.server_selection_timeout_secs.or(fallback.server_selection_timeout_secs),
+            tls: self.tls.or(fallback.tls),
+            tls_ca_file: self.tls_ca_file.or(fallback.tls_ca_file),
+            tls_cert_key_file: self.tls_cert_key_file.or(fallback.tls_cert_key_file),
+            tls_allow_invalid_certificates: self.tls_allow_invalid_certificates.or(fallback.tls_allow_invalid_certificates),
+        }
+    }
+
+    fn from_env() -> Result<DbConfig, String> {
+        fn var<T: std::str::FromStr>(name: &str) -> Result<Option<T>, String> {
+            match env::var(name) {
+                Ok(value) if !value.trim().is_empty() => value
+                    .trim()
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("{} has an invalid value '{}'", name, value)),
+                _ => Ok(None),
+            }
+        }
+        Ok(DbConfig {
+            uri: var("MONGODB_URL")?,
+            database: var("MONGODB_DATABASE")?,
+            min_pool_size: var("MONGODB_MIN_POOL_SIZE")?,
+            max_pool_size: var("MONGODB_MAX_POOL_SIZE")?,
+            connect_timeout_secs: var("MONGODB_CONNECT_TIMEOUT_SECS")?,
+            server_selection_timeout_secs: var("MONGODB_SERVER_SELECTION_TIMEOUT_SECS")?,
+            tls: var("MONGODB_TLS")?,
+            tls_ca_file: var("MONGODB_TLS_CA_FILE")?,
+            tls_cert_key_file: var("MONGODB_TLS_CERT_KEY_FILE")?,
+            tls_allow_invalid_certificates: var("MONGODB_TLS_ALLOW_INVALID_CERTIFICATES")?,
+        })
+    }
+
+    // Settings for the environment named by APP_ENV (dev, test or prod; prod when unset,
+    // as on an installed copy). Environment variables win over the environment's section,
+    // which wins over the shared part of the config file.
+    pub fn load() -> Result<(String, DbConfig), String> {
+        let environment = env::var("APP_ENV").unwrap_or_else(|_| "prod".to_string());
+        if !matches!(environment.as_str(), "dev" | "test" | "prod") {
+            return Err(format!("APP_ENV must be dev, test or prod, not '{}'", environment));
+        }
+
+        let path = env::var("CATON_DB_CONFIG").map(PathBuf::from).ok().or_else(default_config_path);
+        let mut file = match path.as_ref().map(|p| (p, std::fs::read_to_string(p))) {
+            Some((path, Ok(text))) => serde_json::from_str::<DbConfigFile>(&text)
+                .map_err(|e| format!("Failed to read database config {}: {}", path.display(), e))?,
+            // The file is optional; environment variables alone are enough
+            None => DbConfigFile::default(),
+            Some((_, Err(e))) if e.kind() == std::io::ErrorKind::NotFound => DbConfigFile::default(),
+            Some((path, Err(e))) => {
+                return Err(format!("Failed to open database config {}: {}", path.display(), e))
+            }
+        };
+
+        let section = file.environments.remove(&environment).unwrap_or_default();
+        let config = DbConfig::from_env()?.or(section).or(file.shared);
+        Ok((environment, config))
+    }
+
+    fn client_options_tls(&self) -> Option<Tls> {
+        let wanted = self.tls.unwrap_or(self.tls_ca_file.is_some() || self.tls_cert_key_file.is_some());
+        if !wanted {
+            // Leave TLS to the connection string, e.g. `tls=true` or mongodb+srv
+            return None;
+        }
+        let options = TlsOptions::builder()
+            .ca_file_path(self.tls_ca_file.clone())
+            .cert_key_file_path(self.tls_cert_key_file.clone())
+            .allow_invalid_certificates(self.tls_allow_invalid_certificates)
+            .build();
+        Some(Tls::Enabled(options))
+    }
+}
 
 #[derive(Clone)]
 pub struct DbState {
     pub db: Arc<Database>,
+    pub environment: String, // dev, test or prod
+    pub database: String,
 }
 
-pub async fn init_db() -> Result<DbState, mongodb::error::Error> {
+// Build the one client the whole app shares. The driver pools connections
+// inside it, so commands reuse `DbState` rather than connecting again.
+pub async fn init_db() -> Result<DbState, String> {
     dotenv().ok(); // Load environment variables from .env file
 
-    // Get MongoDB URI from environment
-    let mongo_uri = env::var("MONGODB_URL").expect("MONGODB_URI must be set in .env file");
+    let (environment, config) = DbConfig::load()?;
+    let uri = config
+        .uri
+        .clone()
+        .ok_or("Set MONGODB_URL in .env or `uri` in the database config file")?;
 
-    // Initialize MongoDB client
-    let client = Client::with_uri_str(&mongo_uri).await?;
-    let database = client.database("users_db");
+    let mut options = ClientOptions::parse(&uri)
+        .await
+        .map_err(|e| format!("Invalid MongoDB connection string: {}", e))?;
+    options.app_name = Some(format!("caton-{}", environment));
+    if config.min_pool_size.is_some() {
+        options.min_pool_size = config.min_pool_size;
+    }
+    if config.max_pool_size.is_some() {
+        options.max_pool_size = config.max_pool_size;
+    }
+    if let Some(secs) = config.connect_timeout_secs {
+        options.connect_timeout = Some(Duration::from_secs(secs));
+    }
+    if let Some(secs) = config.server_selection_timeout_secs {
+        options.server_selection_timeout = Some(Duration::from_secs(secs));
+    }
+    if let Some(tls) = config.client_options_tls() {
+        options.tls = Some(tls);
+    }
+
+    let client = Client::with_options(options).map_err(|e| e.to_string())?;
+    let name = config.database.clone().unwrap_or_else(|| default_database(&environment).to_string());
+    let database = client.database(&name);
 
     Ok(DbState {
         db: Arc::new(database),
+        environment,
+        database: name,
     })
 }
-
@@ -0,0 +1,120 @@
+use std::fmt;
+use mongodb::error::{ErrorKind, WriteFailure};
+use serde::Serialize;
+
+// MongoDB's code for a unique index violation
+pub const DUPLICATE_KEY: i32 = 11000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+// Error returned by Tauri commands. It serialises as
+// `{ "code": "NOT_FOUND", "message": "...", ... }` so the frontend can switch on `code`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "code", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AppError {
+    NotFound { message: String },
+    Validation { message: String, fields: Vec<FieldError> },
+    Conflict { message: String },
+    Unauthorized { message: String }, // Not signed in, wrong credentials or locked out
+    Forbidden { message: String }, // Signed in but the role does not allow it
+    InsufficientStock { message: String, medicine_id: String, available: u32 },
+    Unavailable { message: String }, // The database, mail server or a printer cannot be reached
+    Internal { message: String },
+}
+
+impl AppError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        AppError::NotFound { message: message.into() }
+    }
+
+    // A validation failure on a single field
+    pub fn validation(field: &str, message: impl Into<String>) -> Self {
+        let message = message.into();
+        AppError::Validation {
+            fields: vec![FieldError { field: field.to_string(), message: message.clone() }],
+            message,
+        }
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        AppError::Conflict { message: message.into() }
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        AppError::Unauthorized { message: message.into() }
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        AppError::Forbidden { message: message.into() }
+    }
+
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        AppError::Unavailable { message: message.into() }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        AppError::Internal { message: message.into() }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            AppError::NotFound { message }
+            | AppError::Validation { message, .. }
+            | AppError::Conflict { message }
+            | AppError::Unauthorized { message }
+            | AppError::Forbidden { message }
+            | AppError::InsufficientStock { message, .. }
+            | AppError::Unavailable { message }
+            | AppError::Internal { message } => message,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for AppError {}
+
+// The server's message when `error` is a unique index violation
+pub fn duplicate_key_message(error: &mongodb::error::Error) -> Option<&str> {
+    match error.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == DUPLICATE_KEY => Some(&e.message),
+        _ => None,
+    }
+}
+
+pub fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    duplicate_key_message(error).is_some()
+}
+
+// Raw driver errors are logged, not shown; the UI gets a stable code and a plain message
+impl From<mongodb::error::Error> for AppError {
+    fn from(error: mongodb::error::Error) -> Self {
+        if is_duplicate_key(&error) {
+            return AppError::conflict("A record with the same details already exists");
+        }
+        match error.kind.as_ref() {
+            ErrorKind::ServerSelection { .. } | ErrorKind::Io(_) | ErrorKind::ConnectionPoolCleared { .. } => {
+                log::warn!("Database unavailable: {}", error);
+                AppError::unavailable("The database is not reachable. Please try again.")
+            }
+            _ => {
+                log::error!("Database error: {}", error);
+                AppError::internal("A database error occurred")
+            }
+        }
+    }
+}
+
+impl From<mongodb::bson::oid::Error> for AppError {
+    fn from(_: mongodb::bson::oid::Error) -> Self {
+        AppError::validation("id", "The ID is not valid")
+    }
+}
@@ -0,0 +1,453 @@
+use std::fs::File;
+use std::io::BufWriter;
+use chrono::{Local, NaiveDate};
+use futures::TryStreamExt;
+use mongodb::bson::{self, doc, Document};
+use mongodb::options::FindOptions;
+use mongodb::{Collection, Database};
+use printpdf::{BuiltinFont, IndirectFontRef, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference};
+use rust_xlsxwriter::Workbook;
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use tokio::sync::mpsc;
+use crate::billing::{format_local_date, local_day_bounds, round2, Bill};
+use crate::commands::Medicine;
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::ledger::StockMovement;
+use crate::session::{require_operator, SessionState};
+use crate::settings::load_store_settings;
+
+// Rows buffered between the database cursor and the file writer
+const EXPORT_BUFFER_ROWS: usize = 256;
+
+// A4 landscape layout for PDF exports
+const PAGE_WIDTH: f32 = 297.0;
+const PAGE_HEIGHT: f32 = 210.0;
+const MARGIN: f32 = 10.0;
+const FONT_SIZE: f32 = 8.0;
+const LINE_HEIGHT: f32 = 4.5;
+const CHAR_WIDTH: f32 = 1.6; // Rough width of a Helvetica character at FONT_SIZE
+// printpdf keeps every page in memory until the document is saved, so PDF exports
+// are capped at about 500 pages; CSV and XLSX have no limit
+const PDF_MAX_ROWS: usize = 20_000;
+
+#[derive(Debug, Clone)]
+pub enum ExportCell {
+    Text(String),
+    Number(f64),
+}
+
+impl From<&str> for ExportCell {
+    fn from(value: &str) -> Self {
+        ExportCell::Text(value.to_string())
+    }
+}
+
+impl From<String> for ExportCell {
+    fn from(value: String) -> Self {
+        ExportCell::Text(value)
+    }
+}
+
+impl From<f64> for ExportCell {
+    fn from(value: f64) -> Self {
+        ExportCell::Number(value)
+    }
+}
+
+impl From<i64> for ExportCell {
+    fn from(value: i64) -> Self {
+        ExportCell::Number(value as f64)
+    }
+}
+
+impl ExportCell {
+    fn as_text(&self) -> String {
+        match self {
+            ExportCell::Text(text) => text.clone(),
+            ExportCell::Number(number) => number.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportSummary {
+    pub path: String,
+    pub report: String,
+    pub format: String,
+    pub rows: usize,
+}
+
+// File and document errors are logged; the UI gets a plain message
+fn write_error(error: impl std::fmt::Display) -> AppError {
+    log::error!("Failed to write an export: {}", error);
+    AppError::internal("Failed to write the export file")
+}
+
+// Destination for exported rows; runs on a blocking thread because the writers are synchronous
+trait RowSink {
+    fn write_row(&mut self, row: &[ExportCell]) -> Result<(), AppError>;
+    fn finish(self: Box<Self>) -> Result<(), AppError>;
+}
+
+struct CsvSink {
+    writer: csv::Writer<File>,
+}
+
+impl RowSink for CsvSink {
+    fn write_row(&mut self, row: &[ExportCell]) -> Result<(), AppError> {
+        self.writer
+            .write_record(row.iter().map(ExportCell::as_text))
+            .map_err(write_error)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), AppError> {
+        self.writer.flush().map_err(write_error)
+    }
+}
+
+struct XlsxSink {
+    workbook: Workbook,
+    path: String,
+    next_row: u32,
+}
+
+impl RowSink for XlsxSink {
+    fn write_row(&mut self, row: &[ExportCell]) -> Result<(), AppError> {
+        // Constant-memory worksheets flush each row to disk once the next one starts
+        let sheet = self.workbook.worksheet_from_index(0).map_err(write_error)?;
+        for (column, cell) in row.iter().enumerate() {
+            let column = column as u16;
+            match cell {
+                ExportCell::Text(text) => sheet.write_string(self.next_row, column, text.as_str()),
+                ExportCell::Number(number) => sheet.write_number(self.next_row, column, *number),
+            }
+            .map_err(write_error)?;
+        }
+        self.next_row += 1;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), AppError> {
+        self.workbook.save(&self.path).map_err(write_error)
+    }
+}
+
+// Unlike the CSV and XLSX sinks this one cannot stream: the whole document is
+// built in memory and written out by `finish`
+struct PdfSink {
+    document: PdfDocumentReference,
+    font: IndirectFontRef,
+    layer: PdfLayerReference,
+    headers: Vec<ExportCell>,
+    y: f32,
+    rows: usize,
+    file: BufWriter<File>,
+}
+
+impl PdfSink {
+    fn new(path: &str, title: &str, store: &str) -> Result<Self, AppError> {
+        let file = BufWriter::new(File::create(path).map_err(write_error)?);
+        let (document, page, layer) = PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+        let font = document.add_builtin_font(BuiltinFont::Helvetica).map_err(write_error)?;
+        let layer = document.get_page(page).get_layer(layer);
+        let mut y = PAGE_HEIGHT - MARGIN;
+        if !store.is_empty() {
+            layer.use_text(store, FONT_SIZE + 4.0, Mm(MARGIN), Mm(y), &font);
+            y -= 1.5 * LINE_HEIGHT;
+        }
+        layer.use_text(title, FONT_SIZE + 4.0, Mm(MARGIN), Mm(y), &font);
+
+        Ok(PdfSink {
+            document,
+            font,
+            layer,
+            headers: Vec::new(),
+            y: y - 2.0 * LINE_HEIGHT,
+            rows: 0,
+            file,
+        })
+    }
+
+    fn draw(&mut self, row: &[ExportCell]) {
+        let column_width = (PAGE_WIDTH - 2.0 * MARGIN) / row.len().max(1) as f32;
+        let max_chars = (column_width / CHAR_WIDTH) as usize;
+        for (index, cell) in row.iter().enumerate() {
+            let text: String = cell.as_text().chars().take(max_chars).collect();
+            let x = MARGIN + index as f32 * column_width;
+            self.layer.use_text(text, FONT_SIZE, Mm(x), Mm(self.y), &self.font);
+        }
+        self.y -= LINE_HEIGHT;
+    }
+}
+
+impl RowSink for PdfSink {
+    fn write_row(&mut self, row: &[ExportCell]) -> Result<(), AppError> {
+        self.rows += 1;
+        if self.rows > PDF_MAX_ROWS {
+            return Err(AppError::validation(
+                "format",
+                format!("PDF exports are limited to {} rows; export this report as CSV or XLSX instead", PDF_MAX_ROWS),
+            ));
+        }
+        if self.headers.is_empty() {
+            self.headers = row.to_vec();
+        } else if self.y < MARGIN {
+            // Start a new page and repeat the column headings
+            let (page, layer) = self.document.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+            self.layer = self.document.get_page(page).get_layer(layer);
+            self.y = PAGE_HEIGHT - MARGIN;
+            let headers = self.headers.clone();
+            self.draw(&headers);
+        }
+        self.draw(row);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), AppError> {
+        let sink = *self;
+        let mut file = sink.file;
+        sink.document.save(&mut file).map_err(write_error)
+    }
+}
+
+fn open_sink(format: &str, path: &str, title: &str, store: &str) -> Result<Box<dyn RowSink>, AppError> {
+    match format {
+        "csv" => {
+            let writer = csv::Writer::from_path(path).map_err(write_error)?;
+            Ok(Box::new(CsvSink { writer }))
+        }
+        "xlsx" => {
+            let mut workbook = Workbook::new();
+            let sheet = workbook.add_worksheet_with_constant_memory();
+            // Sheet names are limited to 31 characters
+            sheet
+                .set_name(title.chars().take(31).collect::<String>())
+                .map_err(write_error)?;
+            Ok(Box::new(XlsxSink { workbook, path: path.to_string(), next_row: 0 }))
+        }
+        "pdf" => Ok(Box::new(PdfSink::new(path, title, store)?)),
+        other => Err(AppError::validation(
+            "format",
+            format!("Unknown export format '{}', expected csv, xlsx or pdf", other),
+        )),
+    }
+}
+
+fn created_at_filter(user_id: &str, from: &Option<String>, to: &Option<String>) -> Result<Document, AppError> {
+    let mut filter = doc! { "user_id": user_id };
+    let mut created_at = Document::new();
+    if let Some(from) = from {
+        created_at.insert("$gte", local_day_bounds(from)?.0);
+    }
+    if let Some(to) = to {
+        created_at.insert("$lt", local_day_bounds(to)?.1);
+    }
+    if !created_at.is_empty() {
+        filter.insert("created_at", created_at);
+    }
+    Ok(filter)
+}
+
+type RowSender = mpsc::Sender<Vec<ExportCell>>;
+
+async fn send(tx: &RowSender, row: Vec<ExportCell>) -> Result<(), AppError> {
+    tx.send(row).await.map_err(|_| AppError::internal("The export file writer stopped unexpectedly"))
+}
+
+async fn stream_inventory(db: &Database, user_id: &str, tx: &RowSender) -> Result<(), AppError> {
+    send(tx, vec![
+        "Name".into(), "Batch".into(), "Category".into(), "Expiry".into(), "Quantity".into(),
+        "Purchase price".into(), "Selling price".into(), "Supplier".into(),
+        "Value at cost".into(), "Value at selling price".into(),
+    ]).await?;
+
+    let collection: Collection<Medicine> = db.collection("medicines");
+    let options = FindOptions::builder().sort(doc! { "name": 1, "batch_number": 1 }).build();
+    let mut cursor = collection
+        .find(doc! { "user_id": user_id, "deleted_at": null }, options)
+        .await?;
+    while let Some(m) = cursor.try_next().await? {
+        let quantity = m.quantity as f64;
+        send(tx, vec![
+            m.name.into(), m.batch_number.into(), m.category.unwrap_or_default().into(),
+            m.expiry_date.into(), (m.quantity as i64).into(), m.purchase_price.into(),
+            m.selling_price.into(), m.wholesaler_name.into(),
+            round2(quantity * m.purchase_price).into(), round2(quantity * m.selling_price).into(),
+        ]).await?;
+    }
+    Ok(())
+}
+
+async fn stream_stock_ledger(db: &Database, filter: Document, tx: &RowSender) -> Result<(), AppError> {
+    send(tx, vec![
+        "Date".into(), "Medicine".into(), "Batch".into(), "Change".into(),
+        "Reason".into(), "Reference".into(), "Unit cost".into(),
+    ]).await?;
+
+    let collection: Collection<StockMovement> = db.collection("stock_ledger");
+    let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+    let mut cursor = collection.find(filter, options).await?;
+    while let Some(m) = cursor.try_next().await? {
+        send(tx, vec![
+            format_local_date(m.created_at).into(), m.name.into(), m.batch_number.into(),
+            m.change.into(), m.reason.into(), m.reference.unwrap_or_default().into(), m.unit_cost.into(),
+        ]).await?;
+    }
+    Ok(())
+}
+
+async fn stream_bill_register(db: &Database, filter: Document, tx: &RowSender) -> Result<(), AppError> {
+    send(tx, vec![
+        "Date".into(), "Bill number".into(), "Customer".into(), "Patient".into(),
+        "Subtotal".into(), "Tax".into(), "Round off".into(), "Total".into(), "Payment modes".into(),
+    ]).await?;
+
+    let collection: Collection<Bill> = db.collection("bills");
+    let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+    let mut cursor = collection.find(filter, options).await?;
+    while let Some(bill) = cursor.try_next().await? {
+        let modes: Vec<String> = bill
+            .payments
+            .iter()
+            .map(|p| format!("{:?} {:.2}", p.mode, p.amount).to_lowercase())
+            .collect();
+        send(tx, vec![
+            format_local_date(bill.created_at).into(), bill.bill_number.into(), bill.customer_name.into(),
+            bill.patient_name.unwrap_or_default().into(), bill.subtotal.into(), bill.tax_total.into(),
+            bill.round_off.into(), bill.total.into(), modes.join(", ").into(),
+        ]).await?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct TaxRow {
+    #[serde(rename = "_id")]
+    tax_rate: f64,
+    taxable: f64,
+    tax: f64,
+}
+
+async fn stream_tax_summary(db: &Database, filter: Document, tx: &RowSender) -> Result<(), AppError> {
+    send(tx, vec![
+        "Tax rate %".into(), "Taxable value".into(), "Tax".into(), "Total".into(),
+    ]).await?;
+
+    let collection: Collection<Document> = db.collection("bills");
+    let pipeline = vec![
+        doc! { "$match": filter },
+        doc! { "$unwind": "$items" },
+        doc! {
+            "$group": {
+                "_id": "$items.tax_rate",
+                "taxable": { "$sum": { "$subtract": ["$items.amount", "$items.tax_amount"] } },
+                "tax": { "$sum": "$items.tax_amount" }
+            }
+        },
+        doc! { "$sort": { "_id": 1 } },
+    ];
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    while let Some(document) = cursor.try_next().await? {
+        let row: TaxRow = bson::from_document(document).map_err(|e| {
+            log::error!("Unexpected tax summary row: {}", e);
+            AppError::internal("Failed to read the tax summary")
+        })?;
+        send(tx, vec![
+            row.tax_rate.into(), round2(row.taxable).into(), round2(row.tax).into(),
+            round2(row.taxable + row.tax).into(),
+        ]).await?;
+    }
+    Ok(())
+}
+
+async fn stream_expiry(db: &Database, user_id: &str, until: &str, tx: &RowSender) -> Result<(), AppError> {
+    send(tx, vec![
+        "Expiry".into(), "Days left".into(), "Name".into(), "Batch".into(),
+        "Quantity".into(), "Supplier".into(), "Value at cost".into(),
+    ]).await?;
+
+    let today = Local::now().date_naive();
+    let collection: Collection<Medicine> = db.collection("medicines");
+    let filter = doc! { "user_id": user_id, "deleted_at": null, "quantity": { "$gt": 0 }, "expiry_date": { "$lte": until } };
+    let options = FindOptions::builder().sort(doc! { "expiry_date": 1, "name": 1 }).build();
+    let mut cursor = collection.find(filter, options).await?;
+    while let Some(m) = cursor.try_next().await? {
+        let days_left = NaiveDate::parse_from_str(&m.expiry_date, "%Y-%m-%d")
+            .map(|expiry| ExportCell::from((expiry - today).num_days()))
+            .unwrap_or_else(|_| "".into());
+        send(tx, vec![
+            m.expiry_date.into(), days_left, m.name.into(), m.batch_number.into(),
+            (m.quantity as i64).into(), m.wholesaler_name.into(),
+            round2(m.quantity as f64 * m.purchase_price).into(),
+        ]).await?;
+    }
+    Ok(())
+}
+
+// Write a report to a CSV, XLSX or PDF file at `path`. Rows are streamed from the
+// database cursor so large registers never sit in memory at once, except in a PDF,
+// which is held until it is saved and so is limited to PDF_MAX_ROWS rows.
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub async fn export_report(
+    user_id: String,
+    report: String,
+    format: String,
+    path: String,
+    from: Option<String>,
+    to: Option<String>,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<ExportSummary, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let title = match report.as_str() {
+        "inventory" => "Inventory",
+        "stock_ledger" => "Stock ledger",
+        "bills" => "Bill register",
+        "tax_summary" => "Tax summary",
+        "expiry" => "Expiry report",
+        other => return Err(AppError::validation("report", format!("Unknown report '{}'", other))),
+    };
+    let filter = created_at_filter(&user_id, &from, &to)?;
+    // Expiry reports look ahead 90 days unless an end date is given
+    let expiry_until = to
+        .clone()
+        .unwrap_or_else(|| (Local::now().date_naive() + chrono::Duration::days(90)).format("%Y-%m-%d").to_string());
+
+    let store = load_store_settings(&db.db, &user_id).await?.legal_name;
+
+    let (tx, mut rx) = mpsc::channel::<Vec<ExportCell>>(EXPORT_BUFFER_ROWS);
+    let writer = {
+        let (format, path, title) = (format.clone(), path.clone(), title.to_string());
+        tokio::task::spawn_blocking(move || -> Result<usize, AppError> {
+            let mut sink = open_sink(&format, &path, &title, &store)?;
+            let mut rows: usize = 0;
+            while let Some(row) = rx.blocking_recv() {
+                sink.write_row(&row)?;
+                rows += 1;
+            }
+            sink.finish()?;
+            Ok(rows.saturating_sub(1)) // Not counting the header
+        })
+    };
+
+    let streamed = match report.as_str() {
+        "inventory" => stream_inventory(&db.db, &user_id, &tx).await,
+        "stock_ledger" => stream_stock_ledger(&db.db, filter, &tx).await,
+        "bills" => stream_bill_register(&db.db, filter, &tx).await,
+        "tax_summary" => stream_tax_summary(&db.db, filter, &tx).await,
+        _ => stream_expiry(&db.db, &user_id, &expiry_until, &tx).await,
+    };
+    drop(tx);
+
+    // A writer failure (bad path, disk full) is the more useful error to report
+    let rows = writer.await.map_err(|e| {
+        log::error!("Export writer task failed: {}", e);
+        AppError::internal("The export file writer stopped unexpectedly")
+    })??;
+    streamed?;
+
+    Ok(ExportSummary { path, report, format, rows })
+}
@@ -0,0 +1,675 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use calamine::{open_workbook_auto, Data, DataType, Reader};
+use chrono::NaiveDate;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use mongodb::error::ErrorKind;
+use mongodb::options::InsertManyOptions;
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::billing::round2;
+use crate::commands::Medicine;
+use crate::db::DbState;
+use crate::error::{AppError, DUPLICATE_KEY};
+use crate::ledger::{record_movements, StockMovement};
+use crate::purchases::{prepare_purchase_invoice, save_purchase_invoice, PurchaseInvoice, PurchaseLineInput};
+use crate::session::{require_operator, SessionState};
+use crate::settings::load_store_settings;
+use crate::validation::{validate_medicine, MedicineRules};
+
+// Medicine fields a source column can be mapped to, and whether they must be present
+const IMPORT_FIELDS: [(&str, bool); 10] = [
+    ("name", true),
+    ("batch_number", true),
+    ("expiry_date", true),
+    ("quantity", true),
+    ("purchase_price", true),
+    ("selling_price", true),
+    ("wholesaler_name", true),
+    ("purchase_date", true),
+    ("category", false),
+    ("manufacturer", false),
+];
+
+// Supplier invoice fields a source column can be mapped to. Each row is one invoice line;
+// rows sharing a supplier and invoice number make up one invoice.
+const PURCHASE_IMPORT_FIELDS: [(&str, bool); 9] = [
+    ("supplier_name", true),
+    ("invoice_number", true),
+    ("invoice_date", true),
+    ("due_date", false), // Defaults to the invoice date
+    ("name", true),
+    ("batch_number", true),
+    ("quantity", false),  // Defaults to the batch's quantity
+    ("unit_cost", false), // Defaults to the batch's purchase price
+    ("tax_rate", false),
+];
+
+// Date layouts accepted in import files, normalised to YYYY-MM-DD
+const DATE_FORMATS: [&str; 4] = ["%Y-%m-%d", "%d-%m-%Y", "%d/%m/%Y", "%d.%m.%Y"];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportPreview {
+    pub headers: Vec<String>,
+    pub sample_rows: Vec<Vec<String>>,
+    pub row_count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportRowError {
+    pub row: usize, // 1-based, counting the header as row 1 like a spreadsheet
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub import_id: Option<String>, // Only set once rows have been inserted
+    pub dry_run: bool,
+    pub total_rows: usize,
+    pub valid_rows: usize,
+    pub inserted: usize,
+    pub errors: Vec<ImportRowError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PurchaseImportReport {
+    pub dry_run: bool,
+    pub total_rows: usize,
+    pub invoices: Vec<PurchaseInvoice>, // What would be recorded in a dry run, otherwise what was
+    pub recorded: usize,
+    pub errors: Vec<ImportRowError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportBatch {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub file_name: String,
+    pub row_count: usize,
+    pub created_at: i64,
+    pub undone_at: Option<i64>,
+}
+
+fn cell_to_string(cell: &Data) -> String {
+    match cell {
+        Data::Empty => String::new(),
+        Data::DateTime(_) | Data::DateTimeIso(_) => cell
+            .as_date()
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| cell.to_string()),
+        // Spreadsheets store whole numbers as floats; drop the trailing ".0"
+        Data::Float(f) if f.fract() == 0.0 && f.abs() < 1e15 => format!("{}", *f as i64),
+        other => other.to_string().trim().to_string(),
+    }
+}
+
+// Read the header row and data rows from a CSV or spreadsheet file
+fn read_table(path: &str) -> Result<(Vec<String>, Vec<Vec<String>>), AppError> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let mut rows: Vec<Vec<String>> = match extension.as_str() {
+        "csv" => {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .trim(csv::Trim::All)
+                .from_path(path)
+                .map_err(|e| AppError::validation("path", format!("Failed to open {}: {}", path, e)))?;
+            reader
+                .records()
+                .map(|record| {
+                    record
+                        .map(|r| r.iter().map(str::to_string).collect())
+                        .map_err(|e| AppError::validation("path", format!("Failed to read {}: {}", path, e)))
+                })
+                .collect::<Result<_, _>>()?
+        }
+        "xlsx" | "xlsm" | "xls" | "ods" => {
+            let mut workbook = open_workbook_auto(path).map_err(|e| AppError::validation("path", format!("Failed to open {}: {}", path, e)))?;
+            let range = workbook
+                .worksheet_range_at(0)
+                .ok_or_else(|| AppError::validation("path", "The workbook has no sheets"))?
+                .map_err(|e| AppError::validation("path", format!("Failed to read {}: {}", path, e)))?;
+            range.rows().map(|row| row.iter().map(cell_to_string).collect()).collect()
+        }
+        _ => return Err(AppError::validation("path", "Only .csv, .xlsx, .xls and .ods files can be imported")),
+    };
+
+    // Spreadsheets often carry trailing blank rows
+    rows.retain(|row| row.iter().any(|cell| !cell.is_empty()));
+    if rows.is_empty() {
+        return Err(AppError::validation("path", "The file is empty"));
+    }
+    let headers = rows.remove(0);
+    Ok((headers, rows))
+}
+
+pub fn parse_import_date(value: &str) -> Option<String> {
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+        .map(|d| d.format("%Y-%m-%d").to_string())
+}
+
+fn parse_price(value: &str) -> Option<f64> {
+    value
+        .replace(',', "")
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite() && *v >= 0.0)
+        .map(round2)
+}
+
+// Find the column each field is mapped to; every required field must have one
+fn map_columns(
+    fields: &[(&'static str, bool)],
+    headers: &[String],
+    mapping: &HashMap<String, String>,
+) -> Result<HashMap<&'static str, usize>, AppError> {
+    let mut columns = HashMap::new();
+    for &(field, required) in fields {
+        let position = mapping
+            .get(field)
+            .and_then(|column| headers.iter().position(|h| h.eq_ignore_ascii_case(column.trim())));
+        match position {
+            Some(index) => {
+                columns.insert(field, index);
+            }
+            None if required => {
+                return Err(AppError::validation(
+                    "mapping",
+                    format!("Field '{}' is not mapped to a column in the file", field),
+                ))
+            }
+            None => {}
+        }
+    }
+    Ok(columns)
+}
+
+// Turn every row into a medicine, collecting all problems instead of stopping at the first.
+// Rows are held to the same rules as a batch entered by hand.
+async fn validate_rows(
+    db: &DbState,
+    user_id: &str,
+    headers: &[String],
+    rows: &[Vec<String>],
+    mapping: &HashMap<String, String>,
+) -> Result<(Vec<(usize, Medicine)>, Vec<ImportRowError>), AppError> {
+    let columns = map_columns(&IMPORT_FIELDS, headers, mapping)?;
+
+    // Trashed batches do not count; the batch index lets them be entered again
+    let collection: Collection<Medicine> = db.db.collection("medicines");
+    let existing: HashSet<(String, String)> = collection
+        .find(doc! { "user_id": user_id, "deleted_at": null }, None)
+        .await?
+        .try_collect::<Vec<Medicine>>()
+        .await?
+        .into_iter()
+        .map(|m| (m.name.to_lowercase(), m.batch_number.to_lowercase()))
+        .collect();
+    let rules = MedicineRules::from(&load_store_settings(&db.db, user_id).await?);
+
+    let mut seen: HashSet<(String, String)> = HashSet::new();
+    let mut medicines = Vec::new();
+    let mut errors = Vec::new();
+
+    for (index, row) in rows.iter().enumerate() {
+        let row_number = index + 2;
+        let value = |field: &str| -> String {
+            columns
+                .get(field)
+                .and_then(|&i| row.get(i))
+                .map(|v| v.trim().to_string())
+                .unwrap_or_default()
+        };
+        let error_count = errors.len();
+        let mut fail = |field: &str, message: String| {
+            errors.push(ImportRowError { row: row_number, field: field.to_string(), message });
+        };
+
+        let name = value("name");
+        let batch_number = value("batch_number");
+        for (field, text) in [("name", &name), ("batch_number", &batch_number)] {
+            if text.is_empty() {
+                fail(field, "is required".to_string());
+            }
+        }
+
+        let expiry_date = parse_import_date(&value("expiry_date"));
+        if expiry_date.is_none() {
+            fail("expiry_date", format!("'{}' is not a valid date", value("expiry_date")));
+        }
+        let purchase_date = parse_import_date(&value("purchase_date"));
+        if purchase_date.is_none() {
+            fail("purchase_date", format!("'{}' is not a valid date", value("purchase_date")));
+        }
+
+        let quantity = value("quantity").parse::<u32>().ok();
+        if quantity.is_none() {
+            fail("quantity", format!("'{}' is not a whole number", value("quantity")));
+        }
+        let purchase_price = parse_price(&value("purchase_price"));
+        let selling_price = parse_price(&value("selling_price"));
+        for (field, price) in [("purchase_price", purchase_price), ("selling_price", selling_price)] {
+            if price.is_none() {
+                fail(field, format!("'{}' is not a valid amount", value(field)));
+            }
+        }
+
+        let key = (name.to_lowercase(), batch_number.to_lowercase());
+        if !name.is_empty() && !batch_number.is_empty() {
+            if existing.contains(&key) {
+                fail("batch_number", format!("{} batch {} already exists", name, batch_number));
+            } else if !seen.insert(key) {
+                fail("batch_number", format!("{} batch {} appears more than once in the file", name, batch_number));
+            }
+        }
+
+        if errors.len() > error_count {
+            continue;
+        }
+        let category = value("category");
+        let manufacturer = value("manufacturer");
+        let medicine = Medicine {
+            id: None,
+            name,
+            batch_number,
+            expiry_date: expiry_date.unwrap_or_default(),
+            quantity: quantity.unwrap_or_default(),
+            purchase_price: purchase_price.unwrap_or_default(),
+            selling_price: selling_price.unwrap_or_default(),
+            wholesaler_name: value("wholesaler_name"),
+            purchase_date: purchase_date.unwrap_or_default(),
+            user_id: user_id.to_string(),
+            category: if category.is_empty() { None } else { Some(category) },
+            manufacturer: if manufacturer.is_empty() { None } else { Some(manufacturer) },
+            import_id: None,
+            version: 0,
+            deleted_at: None,
+            deleted_by: None,
+        };
+        match validate_medicine(&medicine, &rules) {
+            Ok(_) => medicines.push((row_number, medicine)),
+            Err(AppError::Validation { fields, .. }) => errors.extend(fields.into_iter().map(|f| ImportRowError {
+                row: row_number,
+                field: f.field,
+                message: f.message,
+            })),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok((medicines, errors))
+}
+
+// Show the columns and first rows of a file so the user can map them to medicine fields
+#[command]
+pub async fn preview_import_file(
+    user_id: String,
+    path: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<ImportPreview, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let (headers, rows) = read_table(&path)?;
+    Ok(ImportPreview {
+        headers,
+        row_count: rows.len(),
+        sample_rows: rows.into_iter().take(5).collect(),
+    })
+}
+
+// Validate a CSV/XLSX file of batches and, unless it is a dry run or has errors,
+// insert every row under a single import id that `undo_import` can roll back.
+// Rows the database still rejects are reported and the others stay imported.
+#[command]
+pub async fn import_medicines(
+    user_id: String,
+    path: String,
+    mapping: HashMap<String, String>, // Medicine field -> column header in the file
+    dry_run: bool,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<ImportReport, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let (headers, rows) = read_table(&path)?;
+    let (valid, errors) = validate_rows(&db, &user_id, &headers, &rows, &mapping).await?;
+    let (row_numbers, mut medicines): (Vec<usize>, Vec<Medicine>) = valid.into_iter().unzip();
+
+    let mut report = ImportReport {
+        import_id: None,
+        dry_run,
+        total_rows: rows.len(),
+        valid_rows: medicines.len(),
+        inserted: 0,
+        errors,
+    };
+    if dry_run || !report.errors.is_empty() || medicines.is_empty() {
+        return Ok(report);
+    }
+
+    let imports: Collection<ImportBatch> = db.db.collection("imports");
+    let batch = ImportBatch {
+        id: None,
+        user_id: user_id.clone(),
+        file_name: Path::new(&path)
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        row_count: medicines.len(),
+        created_at: DateTime::now().timestamp_millis(),
+        undone_at: None,
+    };
+    let batch_id = imports
+        .insert_one(&batch, None)
+        .await?
+        .inserted_id
+        .as_object_id()
+        .ok_or_else(|| AppError::internal("Failed to record the import"))?;
+    let import_id = batch_id.to_hex();
+
+    for medicine in medicines.iter_mut() {
+        medicine.import_id = Some(import_id.clone());
+    }
+    // Unordered, so a row the database rejects does not stop the rows after it
+    let collection: Collection<Medicine> = db.db.collection("medicines");
+    let options = InsertManyOptions::builder().ordered(false).build();
+    let stopped = match collection.insert_many(&medicines, options).await {
+        Ok(_) => None,
+        Err(e) => match e.kind.as_ref() {
+            ErrorKind::BulkWrite(failure) if failure.write_concern_error.is_none() => {
+                for write_error in failure.write_errors.iter().flatten() {
+                    let (Some(medicine), Some(&row)) = (medicines.get(write_error.index), row_numbers.get(write_error.index))
+                    else {
+                        continue;
+                    };
+                    let (field, message) = if write_error.code == DUPLICATE_KEY {
+                        ("batch_number", format!("{} batch {} already exists", medicine.name, medicine.batch_number))
+                    } else {
+                        log::error!("Import {} row {} was rejected: {}", import_id, row, write_error.message);
+                        ("row", "This row could not be saved".to_string())
+                    };
+                    report.errors.push(ImportRowError { row, field: field.to_string(), message });
+                }
+                None
+            }
+            _ => Some(AppError::from(e)),
+        },
+    };
+
+    // Every row carries the import id, so what went in can be found even after a failure,
+    // and is recorded and left for `undo_import`
+    let inserted: Vec<Medicine> = collection
+        .find(doc! { "user_id": &user_id, "import_id": &import_id }, None)
+        .await?
+        .try_collect()
+        .await?;
+    let receipts = inserted
+        .iter()
+        .map(|m| StockMovement::for_batch(m, m.quantity as i64, "receipt", Some(format!("import {}", import_id))))
+        .collect();
+    if let Err(e) = record_movements(&db.db, receipts).await {
+        log::error!("Import {} was saved but its receipts were not recorded: {}", import_id, e);
+    }
+    if inserted.len() != medicines.len() {
+        imports
+            .update_one(doc! { "_id": batch_id }, doc! { "$set": { "row_count": inserted.len() as i64 } }, None)
+            .await?;
+    }
+    if let Some(error) = stopped {
+        return Err(AppError::internal(format!(
+            "{} The import stopped after {} of {} rows; undo import {} and try again.",
+            error,
+            inserted.len(),
+            medicines.len(),
+            import_id
+        )));
+    }
+
+    report.inserted = inserted.len();
+    report.import_id = Some(import_id);
+    Ok(report)
+}
+
+// Remove every batch created by an import, as long as none of them has been billed
+#[command]
+pub async fn undo_import(
+    user_id: String,
+    import_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<String, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let imports: Collection<ImportBatch> = db.db.collection("imports");
+    let object_id = ObjectId::parse_str(&import_id)?;
+    let batch = imports
+        .find_one(doc! { "_id": object_id, "user_id": &user_id }, None)
+        .await?
+        .ok_or_else(|| AppError::not_found("No import found for the specified user and ID."))?;
+    if batch.undone_at.is_some() {
+        return Err(AppError::conflict("This import has already been undone"));
+    }
+
+    let collection: Collection<Medicine> = db.db.collection("medicines");
+    let filter = doc! { "user_id": &user_id, "import_id": &import_id };
+    let imported: Vec<Medicine> = collection
+        .find(filter.clone(), None)
+        .await?
+        .try_collect()
+        .await?;
+
+    let medicine_ids: Vec<String> = imported.iter().filter_map(|m| m.id.map(|id| id.to_hex())).collect();
+    let bills: Collection<mongodb::bson::Document> = db.db.collection("bills");
+    let billed = bills
+        .count_documents(doc! { "user_id": &user_id, "items.medicine_id": { "$in": &medicine_ids } }, None)
+        .await?;
+    if billed > 0 {
+        return Err(AppError::conflict(format!(
+            "Cannot undo the import: its batches appear on {} bill(s)",
+            billed
+        )));
+    }
+
+    let removed = collection.delete_many(filter, None).await?;
+    // Trashed batches already recorded their stock leaving when they were deleted
+    let removals = imported
+        .iter()
+        .filter(|m| m.deleted_at.is_none())
+        .map(|m| StockMovement::for_batch(m, -(m.quantity as i64), "delete", Some(format!("undo import {}", import_id))))
+        .collect();
+    record_movements(&db.db, removals).await?;
+
+    imports
+        .update_one(
+            doc! { "_id": object_id },
+            doc! { "$set": { "undone_at": DateTime::now().timestamp_millis() } },
+            None,
+        )
+        .await?;
+
+    Ok(format!("Removed {} imported batches.", removed.deleted_count))
+}
+
+// Rows of one supplier invoice in the file, in file order
+struct InvoiceRows {
+    first_row: usize,
+    supplier_name: String,
+    invoice_number: String,
+    invoice_date: String,
+    due_date: String,
+    lines: Vec<PurchaseLineInput>,
+}
+
+// Validate a CSV/XLSX file of supplier invoice lines and, unless it is a dry run or has
+// errors, record each invoice. Invoices are checked exactly as `record_purchase_invoice`
+// checks them; the batches must already be in stock.
+#[command]
+pub async fn import_purchase_invoices(
+    user_id: String,
+    path: String,
+    mapping: HashMap<String, String>, // Invoice field -> column header in the file
+    dry_run: bool,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<PurchaseImportReport, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let (headers, rows) = read_table(&path)?;
+    let columns = map_columns(&PURCHASE_IMPORT_FIELDS, &headers, &mapping)?;
+
+    // Batches are matched by product name and batch number, ignoring case
+    let collection: Collection<Medicine> = db.db.collection("medicines");
+    let batches: HashMap<(String, String), String> = collection
+        .find(doc! { "user_id": &user_id, "deleted_at": null }, None)
+        .await?
+        .try_collect::<Vec<Medicine>>()
+        .await?
+        .into_iter()
+        .filter_map(|m| Some(((m.name.to_lowercase(), m.batch_number.to_lowercase()), m.id?.to_hex())))
+        .collect();
+
+    let mut invoices: Vec<InvoiceRows> = Vec::new();
+    let mut errors = Vec::new();
+    for (index, row) in rows.iter().enumerate() {
+        let row_number = index + 2;
+        let value = |field: &str| -> String {
+            columns
+                .get(field)
+                .and_then(|&i| row.get(i))
+                .map(|v| v.trim().to_string())
+                .unwrap_or_default()
+        };
+        let error_count = errors.len();
+        let mut fail = |field: &str, message: String| {
+            errors.push(ImportRowError { row: row_number, field: field.to_string(), message });
+        };
+
+        for field in ["supplier_name", "invoice_number", "name", "batch_number"] {
+            if value(field).is_empty() {
+                fail(field, "is required".to_string());
+            }
+        }
+        let invoice_date = parse_import_date(&value("invoice_date"));
+        if invoice_date.is_none() {
+            fail("invoice_date", format!("'{}' is not a valid date", value("invoice_date")));
+        }
+        let due_date = match value("due_date").as_str() {
+            "" => invoice_date.clone(),
+            text => parse_import_date(text),
+        };
+        if due_date.is_none() {
+            fail("due_date", format!("'{}' is not a valid date", value("due_date")));
+        }
+        let quantity = match value("quantity").as_str() {
+            "" => Some(None),
+            text => text.parse::<u32>().ok().map(Some),
+        };
+        if quantity.is_none() {
+            fail("quantity", format!("'{}' is not a whole number", value("quantity")));
+        }
+        let mut amount = |field: &str| match value(field).as_str() {
+            "" => Some(None),
+            text => parse_price(text).map(Some).or_else(|| {
+                fail(field, format!("'{}' is not a valid amount", text));
+                None
+            }),
+        };
+        let unit_cost = amount("unit_cost");
+        let tax_rate = amount("tax_rate");
+
+        let (name, batch_number) = (value("name"), value("batch_number"));
+        let medicine_id = batches.get(&(name.to_lowercase(), batch_number.to_lowercase()));
+        if medicine_id.is_none() && !name.is_empty() && !batch_number.is_empty() {
+            fail("batch_number", format!("{} batch {} is not in stock", name, batch_number));
+        }
+
+        if errors.len() > error_count {
+            continue;
+        }
+        let (supplier_name, invoice_number) = (value("supplier_name"), value("invoice_number"));
+        let (invoice_date, due_date) = (invoice_date.unwrap_or_default(), due_date.unwrap_or_default());
+        let line = PurchaseLineInput {
+            medicine_id: medicine_id.cloned().unwrap_or_default(),
+            quantity: quantity.flatten(),
+            unit_cost: unit_cost.flatten(),
+            tax_rate: tax_rate.flatten(),
+        };
+        let existing = invoices.iter_mut().find(|i| {
+            i.supplier_name.eq_ignore_ascii_case(&supplier_name) && i.invoice_number.eq_ignore_ascii_case(&invoice_number)
+        });
+        match existing {
+            Some(invoice) if invoice.invoice_date != invoice_date || invoice.due_date != due_date => {
+                errors.push(ImportRowError {
+                    row: row_number,
+                    field: "invoice_date".to_string(),
+                    message: format!("Dates differ from row {} of the same invoice", invoice.first_row),
+                });
+            }
+            Some(invoice) => invoice.lines.push(line),
+            None => invoices.push(InvoiceRows {
+                first_row: row_number,
+                supplier_name,
+                invoice_number,
+                invoice_date,
+                due_date,
+                lines: vec![line],
+            }),
+        }
+    }
+
+    let mut prepared = Vec::new();
+    for rows in invoices {
+        let invoice = prepare_purchase_invoice(
+            &db.db,
+            &user_id,
+            &rows.supplier_name,
+            &rows.invoice_number,
+            rows.invoice_date,
+            rows.due_date,
+            rows.lines,
+        )
+        .await;
+        match invoice {
+            Ok(invoice) => prepared.push((rows.first_row, invoice)),
+            Err(e) => errors.push(ImportRowError {
+                row: rows.first_row,
+                field: "invoice_number".to_string(),
+                message: e.message().to_string(),
+            }),
+        }
+    }
+
+    let mut report = PurchaseImportReport {
+        dry_run,
+        total_rows: rows.len(),
+        invoices: Vec::new(),
+        recorded: 0,
+        errors,
+    };
+    if dry_run || !report.errors.is_empty() {
+        report.invoices = prepared.into_iter().map(|(_, invoice)| invoice).collect();
+        return Ok(report);
+    }
+
+    // Each invoice is saved on its own; one that fails is reported and the rest still go in
+    for (first_row, invoice) in prepared {
+        match save_purchase_invoice(&db.db, invoice).await {
+            Ok(invoice) => report.invoices.push(invoice),
+            Err(e) => report.errors.push(ImportRowError {
+                row: first_row,
+                field: "invoice_number".to_string(),
+                message: e.message().to_string(),
+            }),
+        }
+    }
+    report.recorded = report.invoices.len();
+    Ok(report)
+}
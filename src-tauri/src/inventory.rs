@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, HashMap};
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use mongodb::options::{FindOptions, UpdateOptions};
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::billing::round2;
+use crate::commands::Medicine;
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::session::{require_operator, require_role, SessionState};
+use crate::settings::load_store_settings;
+use crate::user::PRIVILEGED_ROLES;
+
+// A product's own reorder level, overriding the store default
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReorderLevel {
+    pub user_id: String,
+    pub name: String, // Product name, lower-cased
+    pub level: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InventoryBatch {
+    pub medicine_id: String,
+    pub batch_number: String,
+    pub expiry_date: String,
+    pub quantity: u32,
+    pub purchase_price: f64,
+    pub selling_price: f64,
+    pub wholesaler_name: String,
+}
+
+// Stock of one product summed over its batches
+#[derive(Debug, Serialize)]
+pub struct InventoryItem {
+    pub name: String,
+    pub category: Option<String>,
+    pub manufacturer: Option<String>,
+    pub total_quantity: u64,
+    pub nearest_expiry: Option<String>, // Earliest expiry among batches still in stock
+    pub value_at_cost: f64,
+    pub value_at_sale: f64,
+    pub reorder_level: u32,
+    pub reorder_status: String, // "out_of_stock", "reorder" or "ok"
+    pub batches: Vec<InventoryBatch>, // Earliest expiry first
+}
+
+// Stock per product for the store, with its batches, value and whether it needs reordering
+#[command]
+pub async fn get_inventory(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<InventoryItem>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let settings = load_store_settings(&db.db, &user_id).await?;
+    let levels: Collection<ReorderLevel> = db.db.collection("reorder_levels");
+    let overrides: HashMap<String, u32> = levels
+        .find(doc! { "user_id": &user_id }, None)
+        .await?
+        .try_collect::<Vec<ReorderLevel>>()
+        .await?
+        .into_iter()
+        .map(|l| (l.name, l.level))
+        .collect();
+
+    let collection: Collection<Medicine> = db.db.collection("medicines");
+    // Sorted by name only so each product takes the same spelling every time; batches
+    // are put in expiry order once they are grouped
+    let options = FindOptions::builder().sort(doc! { "name": 1 }).build();
+    let mut cursor = collection.find(doc! { "user_id": &user_id, "deleted_at": null }, options).await?;
+
+    // Products are keyed case-insensitively so "Paracetamol" and "paracetamol" add up
+    let mut products: BTreeMap<String, InventoryItem> = BTreeMap::new();
+    while let Some(m) = cursor.try_next().await? {
+        let key = m.name.to_lowercase();
+        let reorder_level = overrides.get(&key).copied().unwrap_or(settings.default_reorder_level);
+        let item = products.entry(key).or_insert_with(|| InventoryItem {
+            name: m.name.clone(),
+            category: m.category.clone(),
+            manufacturer: m.manufacturer.clone(),
+            total_quantity: 0,
+            nearest_expiry: None,
+            value_at_cost: 0.0,
+            value_at_sale: 0.0,
+            reorder_level,
+            reorder_status: String::new(),
+            batches: Vec::new(),
+        });
+        item.total_quantity += m.quantity as u64;
+        item.value_at_cost += m.quantity as f64 * m.purchase_price;
+        item.value_at_sale += m.quantity as f64 * m.selling_price;
+        // Expiry dates are YYYY-MM-DD, so the smallest string is the earliest date
+        if m.quantity > 0 && item.nearest_expiry.as_ref().is_none_or(|e| m.expiry_date < *e) {
+            item.nearest_expiry = Some(m.expiry_date.clone());
+        }
+        item.category = item.category.take().or(m.category);
+        item.manufacturer = item.manufacturer.take().or(m.manufacturer);
+        item.batches.push(InventoryBatch {
+            medicine_id: m.id.map(|id| id.to_hex()).unwrap_or_default(),
+            batch_number: m.batch_number,
+            expiry_date: m.expiry_date,
+            quantity: m.quantity,
+            purchase_price: m.purchase_price,
+            selling_price: m.selling_price,
+            wholesaler_name: m.wholesaler_name,
+        });
+    }
+
+    Ok(products
+        .into_values()
+        .map(|mut item| {
+            item.batches
+                .sort_by(|a, b| a.expiry_date.cmp(&b.expiry_date).then_with(|| a.batch_number.cmp(&b.batch_number)));
+            item.value_at_cost = round2(item.value_at_cost);
+            item.value_at_sale = round2(item.value_at_sale);
+            item.reorder_status = if item.total_quantity == 0 {
+                "out_of_stock"
+            } else if item.total_quantity <= item.reorder_level as u64 {
+                "reorder"
+            } else {
+                "ok"
+            }
+            .to_string();
+            item
+        })
+        .collect())
+}
+
+// Set a product's reorder level, or with `None` go back to the store default.
+// Only an owner or manager may change it.
+#[command]
+pub async fn set_reorder_level(
+    user_id: String,
+    name: String,
+    level: Option<u32>,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<(), AppError> {
+    let denied = "Only an owner or manager can change reorder levels";
+    require_role(&db.db, &session, &user_id, &PRIVILEGED_ROLES, denied).await?;
+    let name = name.trim().to_lowercase();
+    if name.is_empty() {
+        return Err(AppError::validation("name", "Product name is required"));
+    }
+
+    let levels: Collection<ReorderLevel> = db.db.collection("reorder_levels");
+    let filter = doc! { "user_id": &user_id, "name": &name };
+    match level {
+        Some(level) => {
+            let options = UpdateOptions::builder().upsert(true).build();
+            levels
+                .update_one(filter, doc! { "$set": { "level": level as i64 } }, options)
+                .await?;
+        }
+        None => {
+            levels.delete_one(filter, None).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use mongodb::options::FindOptions;
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::commands::Medicine;
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::session::{require_operator, SessionState};
+
+// One change to the quantity of a batch. The current quantity of a batch minus
+// every movement after a date gives the quantity it had on that date.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StockMovement {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub medicine_id: String,
+    pub name: String,
+    pub batch_number: String,
+    pub wholesaler_name: String,
+    pub category: Option<String>,
+    pub change: i64, // Positive for stock in, negative for stock out
+    pub unit_cost: f64,
+    pub selling_price: f64,
+    pub reason: String, // "receipt", "sale", "adjustment", "delete" or "restore"
+    pub reference: Option<String>, // Bill number or other source document
+    pub created_at: i64,
+}
+
+impl StockMovement {
+    pub fn for_batch(medicine: &Medicine, change: i64, reason: &str, reference: Option<String>) -> Self {
+        StockMovement {
+            id: None,
+            user_id: medicine.user_id.clone(),
+            medicine_id: medicine.id.map(|id| id.to_hex()).unwrap_or_default(),
+            name: medicine.name.clone(),
+            batch_number: medicine.batch_number.clone(),
+            wholesaler_name: medicine.wholesaler_name.clone(),
+            category: medicine.category.clone(),
+            change,
+            unit_cost: medicine.purchase_price,
+            selling_price: medicine.selling_price,
+            reason: reason.to_string(),
+            reference,
+            created_at: DateTime::now().timestamp_millis(),
+        }
+    }
+}
+
+pub async fn record_movements(db: &Database, movements: Vec<StockMovement>) -> Result<(), AppError> {
+    let movements: Vec<StockMovement> = movements.into_iter().filter(|m| m.change != 0).collect();
+    if movements.is_empty() {
+        return Ok(());
+    }
+    let collection: Collection<StockMovement> = db.collection("stock_ledger");
+    collection.insert_many(movements, None).await?;
+    Ok(())
+}
+
+pub async fn movements_between(
+    db: &Database,
+    user_id: &str,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Vec<StockMovement>, AppError> {
+    let collection: Collection<StockMovement> = db.collection("stock_ledger");
+
+    let mut filter = doc! { "user_id": user_id };
+    let mut created_at = doc! {};
+    if let Some(from) = from {
+        created_at.insert("$gte", from);
+    }
+    if let Some(to) = to {
+        created_at.insert("$lt", to);
+    }
+    if !created_at.is_empty() {
+        filter.insert("created_at", created_at);
+    }
+    let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+
+    Ok(collection.find(filter, options).await?.try_collect().await?)
+}
+
+// Retrieve the stock movements of one batch, oldest first
+#[command]
+pub async fn get_stock_ledger(
+    user_id: String,
+    medicine_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<StockMovement>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let collection: Collection<StockMovement> = db.db.collection("stock_ledger");
+    let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+    Ok(collection
+        .find(doc! { "user_id": &user_id, "medicine_id": &medicine_id }, options)
+        .await?
+        .try_collect()
+        .await?)
+}
@@ -0,0 +1,186 @@
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::model::User;
+use crate::session::{require_role, SessionState};
+use crate::user::normalize_username;
+
+// Failures allowed inside the window before the username is locked
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+const FAILURE_WINDOW_MILLIS: i64 = 15 * 60 * 1000;
+const LOCKOUT_MILLIS: i64 = 15 * 60 * 1000;
+
+// Failed logins for one username, whether or not an account exists for it
+#[derive(Debug, Serialize, Deserialize)]
+struct LoginAttempts {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    username: String,
+    failures: u32,
+    first_failure_at: i64,
+    locked_until: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoginEvent {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub username: String,
+    pub user_id: Option<String>, // Only known when the login succeeds
+    pub outcome: String, // "success", "pin_unlock", "failure" or "locked"
+    pub created_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginEventPage {
+    pub events: Vec<LoginEvent>,
+    pub total_count: u64,
+    pub page: u32,
+    pub limit: u32,
+}
+
+pub async fn record_login_event(
+    db: &Database,
+    username: &str,
+    user_id: Option<String>,
+    outcome: &str,
+) -> Result<(), AppError> {
+    let events: Collection<LoginEvent> = db.collection("login_audit");
+    events
+        .insert_one(
+            LoginEvent {
+                id: None,
+                username: username.to_string(),
+                user_id,
+                outcome: outcome.to_string(),
+                created_at: DateTime::now().timestamp_millis(),
+            },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+// Refuse the attempt while the username is locked out
+pub async fn check_lockout(db: &Database, username: &str) -> Result<(), AppError> {
+    let attempts: Collection<LoginAttempts> = db.collection("login_attempts");
+    let now = DateTime::now().timestamp_millis();
+    let record = attempts
+        .find_one(doc! { "username": username }, None)
+        .await?;
+
+    match record.and_then(|r| r.locked_until) {
+        Some(until) if until > now => {
+            let minutes = (until - now + 59_999) / 60_000;
+            Err(AppError::unauthorized(format!(
+                "Too many failed login attempts. Try again in {} minute(s).",
+                minutes
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Count a failed attempt, locking the username once the limit is reached inside the window.
+// The count is bumped in a single update so concurrent failures cannot overwrite each other.
+pub async fn record_failure(db: &Database, username: &str) -> Result<(), AppError> {
+    let attempts: Collection<LoginAttempts> = db.collection("login_attempts");
+    let now = DateTime::now().timestamp_millis();
+
+    // A new window starts on the first failure, after the old window runs out or once a
+    // lockout has passed; otherwise the failure is added to the current window
+    let restart = doc! { "$or": [
+        { "$eq": [{ "$ifNull": ["$first_failure_at", null] }, null] },
+        { "$gte": [{ "$subtract": [now, "$first_failure_at"] }, FAILURE_WINDOW_MILLIS] },
+        { "$and": [
+            { "$ne": [{ "$ifNull": ["$locked_until", null] }, null] },
+            { "$lte": ["$locked_until", now] },
+        ] },
+    ] };
+    let count = vec![
+        doc! { "$set": { "restart": restart } },
+        doc! { "$set": {
+            "failures": { "$cond": ["$restart", 1_i64, { "$add": ["$failures", 1_i64] }] },
+            "first_failure_at": { "$cond": ["$restart", now, "$first_failure_at"] },
+            "locked_until": { "$cond": ["$restart", null, { "$ifNull": ["$locked_until", null] }] },
+        } },
+        doc! { "$unset": "restart" },
+    ];
+    let options = FindOneAndUpdateOptions::builder()
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .build();
+    let record = attempts
+        .find_one_and_update(doc! { "username": username }, count, options)
+        .await?;
+
+    // Only the failure that reaches the limit starts the lockout
+    if let Some(record) = record.filter(|r| r.failures >= MAX_FAILED_ATTEMPTS && r.locked_until.is_none()) {
+        attempts
+            .update_one(
+                doc! { "_id": record.id, "locked_until": null },
+                doc! { "$set": { "locked_until": now + LOCKOUT_MILLIS } },
+                None,
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn clear_failures(db: &Database, username: &str) -> Result<(), AppError> {
+    let attempts: Collection<LoginAttempts> = db.collection("login_attempts");
+    attempts
+        .delete_one(doc! { "username": username }, None)
+        .await?;
+    Ok(())
+}
+
+// List login attempts on the store's accounts, newest first. Only the store owner may
+// view the audit trail.
+#[command]
+pub async fn get_login_audit(
+    user_id: String,
+    username: Option<String>,
+    page: u32,
+    limit: u32,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<LoginEventPage, AppError> {
+    require_role(&db.db, &session, &user_id, &["owner"], "Only the owner can view the login audit").await?;
+    if page == 0 || limit == 0 {
+        return Err(AppError::validation("page", "Page and limit must be at least 1"));
+    }
+
+    // The owner and their staff; attempts on anyone else's username are not this store's
+    let users: Collection<User> = db.db.collection("users");
+    let owner_id = ObjectId::parse_str(&user_id)?;
+    let mut usernames: Vec<String> = users
+        .find(doc! { "$or": [{ "_id": owner_id }, { "store_id": &user_id }] }, None)
+        .await?
+        .try_collect::<Vec<User>>()
+        .await?
+        .into_iter()
+        .map(|u| u.username)
+        .collect();
+    if let Some(username) = username.filter(|u| !u.trim().is_empty()) {
+        let username = normalize_username(&username);
+        usernames.retain(|u| *u == username);
+    }
+    let filter = doc! { "username": { "$in": usernames } };
+
+    let events: Collection<LoginEvent> = db.db.collection("login_audit");
+    let total_count = events.count_documents(filter.clone(), None).await?;
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": -1, "_id": -1 })
+        .skip(((page - 1) as u64) * limit as u64)
+        .limit(limit as i64)
+        .build();
+    let events: Vec<LoginEvent> = events.find(filter, options).await?.try_collect().await?;
+
+    Ok(LoginEventPage { events, total_count, page, limit })
+}
@@ -0,0 +1,71 @@
+use std::env;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use crate::error::AppError;
+
+// SMTP server used for mail only the recipient may read, such as password reset codes.
+// Set in .env; without SMTP_HOST and SMTP_FROM no mail can be sent.
+struct MailConfig {
+    host: String,
+    port: Option<u16>,
+    username: Option<String>,
+    password: Option<String>,
+    from: String,
+}
+
+impl MailConfig {
+    fn from_env() -> Option<MailConfig> {
+        let var = |name: &str| env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        Some(MailConfig {
+            host: var("SMTP_HOST")?,
+            port: var("SMTP_PORT").and_then(|p| p.parse().ok()),
+            username: var("SMTP_USERNAME"),
+            password: var("SMTP_PASSWORD"),
+            from: var("SMTP_FROM")?,
+        })
+    }
+}
+
+// Whether `send_mail` has a server to send through
+pub fn mail_configured() -> bool {
+    MailConfig::from_env().is_some()
+}
+
+// Send a plain-text message over STARTTLS
+pub async fn send_mail(to: &str, subject: &str, body: String) -> Result<(), AppError> {
+    let config = MailConfig::from_env()
+        .ok_or_else(|| AppError::unavailable("Email is not set up. Set SMTP_HOST and SMTP_FROM in .env."))?;
+    let from: Mailbox = config.from.parse().map_err(|e| {
+        log::error!("Invalid SMTP_FROM '{}': {}", config.from, e);
+        AppError::internal("The sender address in SMTP_FROM is not valid")
+    })?;
+    let to: Mailbox = to
+        .parse()
+        .map_err(|_| AppError::validation("email", format!("'{}' is not a valid email address", to)))?;
+    let message = Message::builder()
+        .from(from)
+        .to(to)
+        .subject(subject)
+        .body(body)
+        .map_err(|e| {
+            log::error!("Failed to build email: {}", e);
+            AppError::internal("Failed to build the email")
+        })?;
+
+    let mut transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host).map_err(|e| {
+        log::error!("Invalid SMTP_HOST '{}': {}", config.host, e);
+        AppError::internal("The mail server in SMTP_HOST is not valid")
+    })?;
+    if let Some(port) = config.port {
+        transport = transport.port(port);
+    }
+    if let (Some(username), Some(password)) = (config.username, config.password) {
+        transport = transport.credentials(Credentials::new(username, password));
+    }
+    transport.build().send(message).await.map_err(|e| {
+        log::error!("Failed to send email: {}", e);
+        AppError::unavailable("The mail server could not be reached. Please try again.")
+    })?;
+    Ok(())
+}
@@ -1,35 +1,180 @@
 mod commands;
-mod database;
 
 mod db;
 mod cmd;
 mod user;
 mod model;
+mod billing;
+mod customers;
+mod purchases;
+mod analytics;
+mod ledger;
+mod valuation;
+mod importer;
+mod export;
+mod printing;
+mod settings;
+mod password;
+mod login_guard;
+mod session;
+mod error;
+mod validation;
+mod trash;
+mod bulk;
+mod migrations;
+mod tenant;
+mod inventory;
+mod mailer;
 use std::env;
 
 use crate::db::init_db;
+use crate::migrations::{migration_status, run_migrations};
+use crate::trash::purge_all_expired;
 use tauri::{Builder, generate_handler};
-use commands::{initialize_db, insert_medicine, get_medicine, update_medicine, delete_medicine, search_medicines};
-use cmd::{signup,login};
+use commands::{initialize_db, insert_medicine, get_medicine, list_medicines, update_medicine, patch_medicine, delete_medicine, search_medicines};
+use cmd::{signup, login, get_profile, update_profile, create_staff_user, get_staff_users};
+use billing::{create_bill, reconcile_cash_drawer, list_bills, get_bill, reprint_bill};
+use customers::{create_customer, get_customers, update_credit_limit, receive_customer_payment, get_receivables_ageing, get_customer_statement};
+use purchases::{record_purchase_invoice, get_purchase_invoices, record_supplier_payment, get_payables_report};
+use analytics::{get_sales_summary, get_top_products, get_slow_movers, get_sales_by_hour};
+use ledger::get_stock_ledger;
+use valuation::get_inventory_valuation;
+use importer::{preview_import_file, import_medicines, import_purchase_invoices, undo_import};
+use export::export_report;
+use printing::{get_invoice_template, print_receipt, render_invoice_pdf, update_invoice_template};
+use settings::{get_store_settings, update_store_settings};
+use password::{get_password_policy, update_password_policy, change_password, issue_password_reset, reset_password};
+use login_guard::get_login_audit;
+use session::{SessionState, set_pin, unlock_with_pin, lock_terminal, record_activity, get_session_status};
+use trash::{get_deleted_medicines, restore_medicine, purge_deleted_medicines};
+use bulk::{preview_bulk_operation, apply_bulk_operation, revert_bulk_operation, get_bulk_operations};
+use tenant::get_categories;
+use inventory::{get_inventory, set_reorder_level};
 
 
 fn main() {
     dotenv::dotenv().ok();
+    // RUST_LOG overrides the level, e.g. RUST_LOG=debug
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
     let db_state = tauri::async_runtime::block_on(init_db())
         .expect("Failed to initialize MongoDB client");
+    log::info!("Using the {} database ({})", db_state.database, db_state.environment);
+
+    // Migrations are run by whoever runs the binary, never from the UI:
+    // `--migration-status` lists them and `--migrations-dry-run` shows what the pending ones would do
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--migration-status") {
+        match tauri::async_runtime::block_on(migration_status(&db_state.db)) {
+            Ok(migrations) => {
+                for m in migrations {
+                    match m.applied_at {
+                        Some(at) => log::info!("{} ({}): applied at {}", m.version, m.name, at),
+                        None => log::info!("{} ({}): pending", m.version, m.name),
+                    }
+                }
+            }
+            Err(e) => log::error!("{}", e),
+        }
+        return;
+    }
+    if args.iter().any(|a| a == "--migrations-dry-run") {
+        match tauri::async_runtime::block_on(run_migrations(&db_state.db, true)) {
+            Ok(pending) if pending.is_empty() => log::info!("No pending migrations"),
+            Ok(pending) => {
+                for m in pending {
+                    log::info!("{} ({}): {}", m.version, m.name, m.summary);
+                }
+            }
+            Err(e) => log::error!("{}", e),
+        }
+        return;
+    }
+    // A failed migration is reported and retried on the next start; the app still opens
+    match tauri::async_runtime::block_on(run_migrations(&db_state.db, false)) {
+        Ok(applied) => {
+            for migration in applied {
+                log::info!("Applied migration {} ({}): {}", migration.version, migration.name, migration.summary);
+            }
+        }
+        Err(e) => log::error!("{}", e),
+    }
+    // Clear out trash past each store's retention period
+    if let Err(e) = tauri::async_runtime::block_on(purge_all_expired(&db_state.db)) {
+        log::error!("Failed to purge deleted medicines: {}", e);
+    }
    
     Builder::default()
         .manage(db_state)
+        .manage(SessionState::default())
         .invoke_handler(generate_handler![
             initialize_db,
             insert_medicine,
             get_medicine,
+            list_medicines,
+            get_categories,
+            get_inventory,
+            set_reorder_level,
             update_medicine,
+            patch_medicine,
             delete_medicine,
+            get_deleted_medicines,
+            restore_medicine,
+            purge_deleted_medicines,
+            preview_bulk_operation,
+            apply_bulk_operation,
+            revert_bulk_operation,
+            get_bulk_operations,
             search_medicines,
             signup,
-            login
+            login,
+            create_bill,
+            reconcile_cash_drawer,
+            list_bills,
+            get_bill,
+            reprint_bill,
+            create_customer,
+            get_customers,
+            update_credit_limit,
+            receive_customer_payment,
+            get_receivables_ageing,
+            get_customer_statement,
+            record_purchase_invoice,
+            get_purchase_invoices,
+            record_supplier_payment,
+            get_payables_report,
+            get_sales_summary,
+            get_top_products,
+            get_slow_movers,
+            get_sales_by_hour,
+            get_stock_ledger,
+            get_inventory_valuation,
+            preview_import_file,
+            import_medicines,
+            import_purchase_invoices,
+            undo_import,
+            export_report,
+            get_invoice_template,
+            update_invoice_template,
+            render_invoice_pdf,
+            print_receipt,
+            get_store_settings,
+            update_store_settings,
+            get_profile,
+            update_profile,
+            create_staff_user,
+            get_staff_users,
+            get_password_policy,
+            update_password_policy,
+            change_password,
+            issue_password_reset,
+            reset_password,
+            get_login_audit,
+            set_pin,
+            unlock_with_pin,
+            lock_terminal,
+            record_activity,
+            get_session_status
         ])
         .run(tauri::generate_context!())
         .expect("Error while running Tauri application");
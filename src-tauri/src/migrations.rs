@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document, Regex};
+use mongodb::error::ErrorKind;
+use mongodb::options::{FindOptions, IndexOptions};
+use mongodb::{Collection, Database, IndexModel};
+use serde::{Deserialize, Serialize};
+use crate::error::AppError;
+use crate::importer::parse_import_date;
+use crate::user::ensure_user_indexes;
+use crate::validation::{ensure_medicine_indexes, BATCH_INDEX};
+
+// Every migration in the order it runs. A version is never reused or reordered;
+// changes to the schema get a new entry at the end.
+const MIGRATIONS: [(u32, &str); 11] = [
+    (1, "user_unique_indexes"),
+    (2, "medicine_batch_unique_index"),
+    (3, "query_indexes"),
+    (4, "unique_document_numbers"),
+    (5, "iso_medicine_dates"),
+    (6, "drop_initialize_db_leftovers"),
+    (7, "category_unique_index"),
+    (8, "reorder_level_unique_index"),
+    (9, "partial_medicine_batch_index"),
+    (10, "login_attempts_unique_username"),
+    (11, "store_defaults_unique_indexes"),
+];
+
+// The batch index before it left out trashed batches
+const FULL_BATCH_INDEX: &str = "store_product_batch_unique";
+
+// Dates stored the way every query expects them, or empty for an unknown purchase date
+const ISO_DATE_PATTERN: &str = r"^(\d{4}-\d{2}-\d{2})?$";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AppliedMigration {
+    #[serde(rename = "_id")]
+    version: u32,
+    name: String,
+    summary: String,
+    applied_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MigrationReport {
+    pub version: u32,
+    pub name: String,
+    pub summary: String, // What was done, or what would be done in a dry run
+}
+
+#[derive(Debug, Serialize)]
+pub struct MigrationStatus {
+    pub version: u32,
+    pub name: String,
+    pub applied_at: Option<i64>, // None while the migration is pending
+}
+
+struct IndexSpec {
+    collection: &'static str,
+    keys: Document,
+    name: &'static str,
+    unique: bool,
+}
+
+fn index(collection: &'static str, keys: Document, name: &'static str, unique: bool) -> IndexSpec {
+    IndexSpec { collection, keys, name, unique }
+}
+
+async fn create_indexes(db: &Database, specs: Vec<IndexSpec>, dry_run: bool) -> Result<String, AppError> {
+    let names: Vec<String> = specs.iter().map(|s| format!("{}.{}", s.collection, s.name)).collect();
+    if dry_run {
+        return Ok(format!("Would create indexes {}", names.join(", ")));
+    }
+    for spec in specs {
+        let collection: Collection<Document> = db.collection(spec.collection);
+        let model = IndexModel::builder()
+            .keys(spec.keys)
+            .options(IndexOptions::builder().unique(spec.unique).name(spec.name.to_string()).build())
+            .build();
+        collection
+            .create_index(model, None)
+            .await
+            .map_err(|e| {
+                log::error!("Failed to create index {}.{}: {}", spec.collection, spec.name, e);
+                AppError::internal(format!("Failed to create index {}.{}", spec.collection, spec.name))
+            })?;
+    }
+    Ok(format!("Created indexes {}", names.join(", ")))
+}
+
+// Drop an index, treating a missing index or collection as already dropped
+async fn drop_index_if_exists(db: &Database, collection: &str, name: &str) -> Result<(), AppError> {
+    let target: Collection<Document> = db.collection(collection);
+    if let Err(e) = target.drop_index(name, None).await {
+        // 26: the collection does not exist yet, 27: the index does not
+        let missing = matches!(e.kind.as_ref(), ErrorKind::Command(c) if c.code == 26 || c.code == 27);
+        if !missing {
+            log::error!("Failed to drop {}.{}: {}", collection, name, e);
+            return Err(AppError::internal(format!("Failed to drop {}.{}", collection, name)));
+        }
+    }
+    Ok(())
+}
+
+// Delete all but one document sharing the same `keys`, so a unique index can be built.
+// The first document in `keep` order survives. Returns how many were (or would be) deleted.
+async fn remove_duplicates(
+    db: &Database,
+    collection: &str,
+    keys: &[&str],
+    keep: Document,
+    dry_run: bool,
+) -> Result<u64, AppError> {
+    let target: Collection<Document> = db.collection(collection);
+    let mut group = Document::new();
+    for key in keys {
+        group.insert(*key, format!("${}", key));
+    }
+    let pipeline = vec![
+        doc! { "$sort": keep },
+        doc! { "$group": { "_id": group, "ids": { "$push": "$_id" } } },
+        doc! { "$match": { "ids.1": { "$exists": true } } },
+    ];
+    let groups: Vec<Document> = target.aggregate(pipeline, None).await?.try_collect().await?;
+    let extra: Vec<Bson> = groups
+        .into_iter()
+        .filter_map(|g| g.get_array("ids").ok().cloned())
+        .flat_map(|ids| ids.into_iter().skip(1))
+        .collect();
+    if dry_run || extra.is_empty() {
+        return Ok(extra.len() as u64);
+    }
+    Ok(target.delete_many(doc! { "_id": { "$in": extra } }, None).await?.deleted_count)
+}
+
+// Concurrent first failures could each insert a record for the same username, splitting
+// its count. Keep the record closest to a lockout and make the username unique.
+async fn unique_login_attempts(db: &Database, dry_run: bool) -> Result<String, AppError> {
+    let keep = doc! { "locked_until": -1, "failures": -1 };
+    let removed = remove_duplicates(db, "login_attempts", &["username"], keep, dry_run).await?;
+    if dry_run {
+        return Ok(format!(
+            "Would remove {} duplicate login_attempts record(s) and replace login_attempts.username with login_attempts.username_unique",
+            removed
+        ));
+    }
+    drop_index_if_exists(db, "login_attempts", "username").await?;
+    let specs = vec![index("login_attempts", doc! { "username": 1 }, "username_unique", true)];
+    create_indexes(db, specs, false).await?;
+    Ok(format!(
+        "Removed {} duplicate login_attempts record(s) and replaced login_attempts.username with login_attempts.username_unique",
+        removed
+    ))
+}
+
+// Setting up a store's defaults upserts its settings and counters, and two logins at
+// once could each insert them. Keep the newest settings and the highest counter, so no
+// document number is handed out twice, and make both unique.
+async fn unique_store_defaults(db: &Database, dry_run: bool) -> Result<String, AppError> {
+    let settings = remove_duplicates(db, "store_settings", &["user_id"], doc! { "updated_at": -1 }, dry_run).await?;
+    let counters = remove_duplicates(db, "counters", &["user_id", "name"], doc! { "value": -1 }, dry_run).await?;
+    let specs = vec![
+        index("store_settings", doc! { "user_id": 1 }, "user_id_unique", true),
+        index("counters", doc! { "user_id": 1, "name": 1 }, "user_name_unique", true),
+    ];
+    let created = create_indexes(db, specs, dry_run).await?;
+    let verb = if dry_run { "would remove" } else { "removed" };
+    Ok(format!(
+        "{}; {} {} duplicate store_settings and {} duplicate counters document(s)",
+        created, verb, settings, counters
+    ))
+}
+
+// Rewrite expiry and purchase dates saved in other layouts, e.g. 31/12/2025, as YYYY-MM-DD
+async fn convert_medicine_dates(db: &Database, dry_run: bool) -> Result<String, AppError> {
+    let medicines: Collection<Document> = db.collection("medicines");
+    let not_iso = || doc! { "$not": Bson::RegularExpression(Regex { pattern: ISO_DATE_PATTERN.to_string(), options: String::new() }) };
+    let filter = doc! { "$or": [{ "expiry_date": not_iso() }, { "purchase_date": not_iso() }] };
+    let options = FindOptions::builder().projection(doc! { "expiry_date": 1, "purchase_date": 1 }).build();
+    let found: Vec<Document> = medicines
+        .find(filter, options)
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut converted = 0;
+    let mut unreadable = 0;
+    for medicine in found {
+        let mut set = Document::new();
+        let mut readable = true;
+        for field in ["expiry_date", "purchase_date"] {
+            let value = medicine.get_str(field).unwrap_or_default().trim();
+            // Older batches may have no purchase date; editing one asks for it
+            if value.is_empty() && field == "purchase_date" {
+                continue;
+            }
+            match parse_import_date(value) {
+                Some(date) if date != value => {
+                    set.insert(field, date);
+                }
+                Some(_) => {}
+                None => readable = false,
+            }
+        }
+        if !readable {
+            unreadable += 1;
+            continue;
+        }
+        if set.is_empty() {
+            continue;
+        }
+        converted += 1;
+        if !dry_run {
+            medicines
+                .update_one(
+                    doc! { "_id": medicine.get("_id") },
+                    doc! { "$set": set, "$inc": { "version": 1_i64 } },
+                    None,
+                )
+                .await
+                ?;
+        }
+    }
+    let verb = if dry_run { "Would convert" } else { "Converted" };
+    Ok(format!(
+        "{} dates on {} batch(es); {} batch(es) have dates that could not be read and were left as they are",
+        verb, converted, unreadable
+    ))
+}
+
+// The old `initialize_db` created a `medicines_<user id>` collection holding only
+// `{ initialized: true }` markers. Drop those collections and any stray markers; a
+// collection holding anything else is left alone and reported.
+async fn drop_initialize_db_leftovers(db: &Database, dry_run: bool) -> Result<String, AppError> {
+    let names = db
+        .list_collection_names(doc! { "name": { "$regex": "^medicines_" } })
+        .await?;
+    let marker = doc! { "initialized": true };
+
+    let mut dropped = Vec::new();
+    let mut kept = Vec::new();
+    for name in names {
+        let collection: Collection<Document> = db.collection(&name);
+        let other = collection
+            .count_documents(doc! { "initialized": { "$ne": true } }, None)
+            .await
+            ?;
+        if other > 0 {
+            kept.push(name);
+            continue;
+        }
+        if !dry_run {
+            collection.drop(None).await?;
+        }
+        dropped.push(name);
+    }
+
+    let medicines: Collection<Document> = db.collection("medicines");
+    let markers = if dry_run {
+        medicines.count_documents(marker, None).await?
+    } else {
+        medicines.delete_many(marker, None).await?.deleted_count
+    };
+
+    let verb = if dry_run { "Would drop" } else { "Dropped" };
+    let mut summary = format!(
+        "{} {} per-user collection(s) and {} marker document(s)",
+        verb,
+        dropped.len(),
+        markers
+    );
+    if !kept.is_empty() {
+        summary.push_str(&format!("; kept {} because they hold other data", kept.join(", ")));
+    }
+    Ok(summary)
+}
+
+// Replace the batch index that covered every batch with one that skips trashed
+// batches, so deleting a batch frees its number. Databases set up after the change
+// already have the new index from migration 2 and nothing to drop.
+async fn partial_batch_index(db: &Database, dry_run: bool) -> Result<String, AppError> {
+    if dry_run {
+        return Ok(format!("Would replace medicines.{} with medicines.{}", FULL_BATCH_INDEX, BATCH_INDEX));
+    }
+    drop_index_if_exists(db, "medicines", FULL_BATCH_INDEX).await?;
+    ensure_medicine_indexes(db).await?;
+    Ok(format!("Replaced medicines.{} with medicines.{}", FULL_BATCH_INDEX, BATCH_INDEX))
+}
+
+async fn apply(version: u32, db: &Database, dry_run: bool) -> Result<String, AppError> {
+    match version {
+        1 if dry_run => Ok("Would normalise usernames and emails and create users.username_unique, users.email_unique".to_string()),
+        1 => ensure_user_indexes(db).await.map(|_| "Normalised usernames and emails and created the unique indexes".to_string()),
+        2 if dry_run => Ok(format!("Would create medicines.{}", BATCH_INDEX)),
+        2 => ensure_medicine_indexes(db).await.map(|_| format!("Created medicines.{}", BATCH_INDEX)),
+        3 => {
+            let specs = vec![
+                index("medicines", doc! { "user_id": 1, "name": 1 }, "user_name", false),
+                index("medicines", doc! { "user_id": 1, "expiry_date": 1 }, "user_expiry_date", false),
+                index("bills", doc! { "user_id": 1, "created_at": -1 }, "user_created_at", false),
+                index("stock_ledger", doc! { "user_id": 1, "created_at": 1 }, "user_created_at", false),
+                index("login_attempts", doc! { "username": 1 }, "username", false),
+            ];
+            create_indexes(db, specs, dry_run).await
+        }
+        4 => {
+            let specs = vec![
+                index("bills", doc! { "user_id": 1, "bill_number": 1 }, "user_bill_number_unique", true),
+                index(
+                    "purchase_invoices",
+                    doc! { "user_id": 1, "supplier_name": 1, "invoice_number": 1 },
+                    "user_supplier_invoice_unique",
+                    true,
+                ),
+            ];
+            create_indexes(db, specs, dry_run).await
+        }
+        5 => convert_medicine_dates(db, dry_run).await,
+        6 => drop_initialize_db_leftovers(db, dry_run).await,
+        7 => {
+            let specs = vec![index("categories", doc! { "user_id": 1, "name": 1 }, "user_name_unique", true)];
+            create_indexes(db, specs, dry_run).await
+        }
+        8 => {
+            let specs = vec![index("reorder_levels", doc! { "user_id": 1, "name": 1 }, "user_name_unique", true)];
+            create_indexes(db, specs, dry_run).await
+        }
+        9 => partial_batch_index(db, dry_run).await,
+        10 => unique_login_attempts(db, dry_run).await,
+        11 => unique_store_defaults(db, dry_run).await,
+        _ => Err(AppError::internal(format!("No migration with version {}", version))),
+    }
+}
+
+// Run every migration not yet recorded as applied, oldest first. Stops at the first
+// failure so later migrations never run ahead of an earlier one. A dry run reports
+// what each pending migration would do and changes nothing.
+pub async fn run_migrations(db: &Database, dry_run: bool) -> Result<Vec<MigrationReport>, AppError> {
+    let applied: Collection<AppliedMigration> = db.collection("schema_migrations");
+    let done: Vec<AppliedMigration> = applied
+        .find(None, None)
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut reports = Vec::new();
+    for (version, name) in MIGRATIONS {
+        if done.iter().any(|m| m.version == version) {
+            continue;
+        }
+        let summary = apply(version, db, dry_run)
+            .await
+            .map_err(|e| AppError::internal(format!("Migration {} ({}) failed: {}", version, name, e)))?;
+        if !dry_run {
+            let record = AppliedMigration {
+                version,
+                name: name.to_string(),
+                summary: summary.clone(),
+                applied_at: Utc::now().timestamp_millis(),
+            };
+            applied.insert_one(record, None).await?;
+        }
+        reports.push(MigrationReport { version, name: name.to_string(), summary });
+    }
+    Ok(reports)
+}
+
+// Every migration and when it was applied, for `--migration-status`
+pub async fn migration_status(db: &Database) -> Result<Vec<MigrationStatus>, AppError> {
+    let applied: Collection<AppliedMigration> = db.collection("schema_migrations");
+    let done: HashMap<u32, i64> = applied
+        .find(None, None)
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?
+        .into_iter()
+        .map(|m| (m.version, m.applied_at))
+        .collect();
+
+    Ok(MIGRATIONS
+        .iter()
+        .map(|(version, name)| MigrationStatus {
+            version: *version,
+            name: name.to_string(),
+            applied_at: done.get(version).copied(),
+        })
+        .collect())
+}
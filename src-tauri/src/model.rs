@@ -20,4 +20,88 @@ pub struct User {
     pub username: String,
     pub password_hash: String,
     pub email: String,
+    #[serde(default = "default_role")]
+    pub role: String, // "owner", "manager", "pharmacist" or "cashier"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store_id: Option<String>, // The owner's ID for staff; None for an owner
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub phone: Option<String>,
+    #[serde(default)]
+    pub qualification: Option<String>, // e.g. "B.Pharm"
+    #[serde(default)]
+    pub registration_number: Option<String>, // Pharmacy council registration
+    #[serde(default)]
+    pub avatar_path: Option<String>, // Image on the local disk
+    #[serde(default)]
+    pub pin_hash: Option<String>, // Short PIN for unlocking a shared terminal
+}
+
+impl User {
+    // The store the account works for. A store is keyed by its owner's ID, which is
+    // the `user_id` every store command takes.
+    pub fn store(&self) -> String {
+        self.store_id
+            .clone()
+            .unwrap_or_else(|| self.id.map(|id| id.to_hex()).unwrap_or_default())
+    }
+
+    // How the user is named on screens and printed bills
+    pub fn signature(&self) -> String {
+        let name = self.display_name.clone().unwrap_or_else(|| self.username.clone());
+        match &self.registration_number {
+            Some(registration) => format!("{} (Reg. {})", name, registration),
+            None => name,
+        }
+    }
+}
+
+// The parts of a user the profile page shows, without the password hash
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub id: String,
+    pub username: String,
+    pub email: String,
+    pub role: String,
+    pub store_id: String,
+    pub display_name: Option<String>,
+    pub phone: Option<String>,
+    pub qualification: Option<String>,
+    pub registration_number: Option<String>,
+    pub avatar_path: Option<String>,
+    pub has_pin: bool,
+}
+
+impl From<User> for UserProfile {
+    fn from(user: User) -> Self {
+        UserProfile {
+            id: user.id.map(|id| id.to_hex()).unwrap_or_default(),
+            store_id: user.store(),
+            username: user.username,
+            email: user.email,
+            role: user.role,
+            display_name: user.display_name,
+            phone: user.phone,
+            qualification: user.qualification,
+            registration_number: user.registration_number,
+            avatar_path: user.avatar_path,
+            has_pin: user.pin_hash.is_some(),
+        }
+    }
+}
+
+// Editable profile fields; a field left out is kept and an empty string clears it
+#[derive(Debug, Deserialize)]
+pub struct ProfileUpdate {
+    pub display_name: Option<String>,
+    pub phone: Option<String>,
+    pub qualification: Option<String>,
+    pub registration_number: Option<String>,
+    pub avatar_path: Option<String>,
+}
+
+// Accounts created before roles existed belong to the store owner
+fn default_role() -> String {
+    "owner".to_string()
 }
@@ -0,0 +1,362 @@
+use bcrypt::{hash, verify, DEFAULT_COST};
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use mongodb::options::{FindOneOptions, ReplaceOptions};
+use mongodb::{Collection, Database};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::login_guard::{check_lockout, clear_failures, record_failure};
+use crate::mailer::{mail_configured, send_mail};
+use crate::model::User;
+use crate::session::{require_operator, require_role, SessionState};
+use crate::user::{dummy_hash, normalize_username};
+
+// Reset codes stop working after this long
+const RESET_CODE_TTL_MILLIS: i64 = 15 * 60 * 1000;
+
+// Rules a new password must satisfy, and the bcrypt cost it is hashed with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    pub bcrypt_cost: u32, // Raising this re-hashes each password at its owner's next login
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 8,
+            require_uppercase: false,
+            require_lowercase: true,
+            require_digit: true,
+            require_symbol: false,
+            bcrypt_cost: DEFAULT_COST,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    // Describe every rule the password breaks, so the user can fix them in one go
+    pub fn check(&self, password: &str) -> Result<(), String> {
+        let mut problems = Vec::new();
+        if password.chars().count() < self.min_length {
+            problems.push(format!("be at least {} characters long", self.min_length));
+        }
+        if self.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+            problems.push("contain an uppercase letter".to_string());
+        }
+        if self.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+            problems.push("contain a lowercase letter".to_string());
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            problems.push("contain a digit".to_string());
+        }
+        if self.require_symbol && !password.chars().any(|c| !c.is_alphanumeric() && !c.is_whitespace()) {
+            problems.push("contain a symbol".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Password must {}", problems.join(", ")))
+        }
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, AppError> {
+        hash(password, self.bcrypt_cost).map_err(|e| {
+            log::error!("Password hashing failed: {}", e);
+            AppError::internal("Failed to hash the password")
+        })
+    }
+}
+
+// The cost a bcrypt hash was made with, read from its "$2b$12$..." prefix
+pub fn hash_cost(password_hash: &str) -> Option<u32> {
+    password_hash.split('$').nth(2)?.parse().ok()
+}
+
+// One store's policy; each store sets its own
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredPolicy {
+    user_id: String,
+    #[serde(flatten)]
+    policy: PasswordPolicy,
+}
+
+// The store's policy. Stores that have not saved one yet keep the single policy
+// every account used before policies were per store, or the defaults.
+pub async fn load_password_policy(db: &Database, store_id: &str) -> Result<PasswordPolicy, AppError> {
+    let collection: Collection<StoredPolicy> = db.collection("password_policies");
+    if let Some(stored) = collection.find_one(doc! { "user_id": store_id }, None).await? {
+        return Ok(stored.policy);
+    }
+    let legacy: Collection<PasswordPolicy> = db.collection("app_settings");
+    let policy = legacy.find_one(doc! { "_id": "password_policy" }, None).await?;
+    Ok(policy.unwrap_or_default())
+}
+
+// The highest cost any store hashes with. An unknown username is checked at this
+// cost so it takes as long to reject as a user of the slowest store.
+pub async fn highest_bcrypt_cost(db: &Database) -> Result<u32, AppError> {
+    let collection: Collection<StoredPolicy> = db.collection("password_policies");
+    let options = FindOneOptions::builder().sort(doc! { "bcrypt_cost": -1 }).build();
+    let stored = collection.find_one(doc! {}, options).await?.map(|s| s.policy.bcrypt_cost);
+    let legacy: Collection<PasswordPolicy> = db.collection("app_settings");
+    let legacy = legacy.find_one(doc! { "_id": "password_policy" }, None).await?.map(|p| p.bcrypt_cost);
+    Ok(stored.into_iter().chain(legacy).fold(DEFAULT_COST, u32::max))
+}
+
+// A one-time code emailed to a user who has forgotten their password
+#[derive(Debug, Serialize, Deserialize)]
+struct PasswordReset {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    user_id: String, // The account being reset
+    issued_by: String,
+    code_hash: String,
+    expires_at: i64,
+    used: bool,
+}
+
+// What the owner is told about a reset; the code itself only goes to the user's email
+#[derive(Debug, Serialize)]
+pub struct IssuedReset {
+    pub username: String,
+    pub sent_to: String, // The email address, partly hidden, e.g. "a***@example.com"
+    pub expires_at: i64,
+}
+
+// Hide most of the local part so the owner can recognise the address without reading it
+fn mask_email(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            let first: String = local.chars().take(1).collect();
+            format!("{}***@{}", first, domain)
+        }
+        None => "***".to_string(),
+    }
+}
+
+async fn set_password(users: &Collection<User>, user: &User, password_hash: String) -> Result<(), AppError> {
+    let object_id = user.id.ok_or_else(|| AppError::internal("Failed to retrieve user ID"))?;
+    users
+        .update_one(doc! { "_id": object_id }, doc! { "$set": { "password_hash": password_hash } }, None)
+        .await?;
+    Ok(())
+}
+
+// Retrieve the password policy that applies to the store's accounts
+#[command]
+pub async fn get_password_policy(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<PasswordPolicy, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    load_password_policy(&db.db, &user_id).await
+}
+
+// Replace the store's password policy. Only the store's owner may change it.
+#[command]
+pub async fn update_password_policy(
+    user_id: String,
+    policy: PasswordPolicy,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<PasswordPolicy, AppError> {
+    require_role(&db.db, &session, &user_id, &["owner"], "Only the owner can change the password policy").await?;
+    if policy.min_length < 6 {
+        return Err(AppError::validation("min_length", "Minimum password length cannot be below 6"));
+    }
+    if !(10..=16).contains(&policy.bcrypt_cost) {
+        return Err(AppError::validation("bcrypt_cost", "bcrypt cost must be between 10 and 16"));
+    }
+
+    let collection: Collection<StoredPolicy> = db.db.collection("password_policies");
+    let stored = StoredPolicy { user_id: user_id.clone(), policy };
+    collection
+        .replace_one(
+            doc! { "user_id": &user_id },
+            &stored,
+            ReplaceOptions::builder().upsert(true).build(),
+        )
+        .await?;
+
+    Ok(stored.policy)
+}
+
+// Change the unlocked user's own password after confirming the current one
+#[command]
+pub async fn change_password(
+    user_id: String,
+    old_password: String,
+    new_password: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<(), AppError> {
+    let users: Collection<User> = db.db.collection("users");
+    let user = require_operator(&db.db, &session, &user_id).await?;
+    if !verify(&old_password, &user.password_hash).unwrap_or(false) {
+        return Err(AppError::validation("old_password", "Current password is incorrect"));
+    }
+    if old_password == new_password {
+        return Err(AppError::validation("new_password", "New password must be different from the current one"));
+    }
+
+    let policy = load_password_policy(&db.db, &user_id).await?;
+    policy
+        .check(&new_password)
+        .map_err(|message| AppError::validation("new_password", message))?;
+    set_password(&users, &user, policy.hash(&new_password)?).await
+}
+
+// Email a one-time reset code to another account in the store. Only the owner may
+// do this. The code goes to the account's own email address and is never returned
+// here; only its hash is stored.
+#[command]
+pub async fn issue_password_reset(
+    user_id: String,
+    username: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<IssuedReset, AppError> {
+    let owner = require_role(&db.db, &session, &user_id, &["owner"], "Only the owner can reset passwords").await?;
+    if !mail_configured() {
+        return Err(AppError::unavailable(
+            "Email is not set up, so reset codes cannot be delivered. Set SMTP_HOST and SMTP_FROM in .env.",
+        ));
+    }
+
+    let users: Collection<User> = db.db.collection("users");
+    let target = users
+        .find_one(doc! { "username": normalize_username(&username) }, None)
+        .await?
+        .filter(|u| u.store() == user_id)
+        .ok_or_else(|| AppError::not_found("No user found in this store for the specified username."))?;
+    let target_id = target.id.ok_or_else(|| AppError::internal("Failed to retrieve user ID"))?.to_hex();
+
+    let policy = load_password_policy(&db.db, &user_id).await?;
+    let code = format!("{:08}", rand::thread_rng().gen_range(0..100_000_000));
+    let code_hash = policy.hash(&code)?;
+    let expires_at = DateTime::now().timestamp_millis() + RESET_CODE_TTL_MILLIS;
+    let resets: Collection<PasswordReset> = db.db.collection("password_resets");
+
+    // Issuing a new code cancels any earlier one for the same account
+    resets
+        .update_many(
+            doc! { "user_id": &target_id, "used": false },
+            doc! { "$set": { "used": true } },
+            None,
+        )
+        .await?;
+    let inserted = resets
+        .insert_one(
+            PasswordReset {
+                id: None,
+                user_id: target_id,
+                issued_by: owner.id.map(|id| id.to_hex()).unwrap_or_default(),
+                code_hash,
+                expires_at,
+                used: false,
+            },
+            None,
+        )
+        .await?;
+
+    let body = format!(
+        "Hello {},\n\nYour password reset code is {}. It expires in {} minutes.\n\
+         If you did not ask for a reset, tell the store owner.",
+        target.username,
+        code,
+        RESET_CODE_TTL_MILLIS / 60_000
+    );
+    if let Err(e) = send_mail(&target.email, "Your password reset code", body).await {
+        // A code nobody received must not stay usable
+        resets
+            .update_one(doc! { "_id": inserted.inserted_id }, doc! { "$set": { "used": true } }, None)
+            .await?;
+        return Err(e);
+    }
+
+    Ok(IssuedReset { username: target.username, sent_to: mask_email(&target.email), expires_at })
+}
+
+// Set a new password using the reset code emailed to the account
+#[command]
+pub async fn reset_password(
+    username: String,
+    code: String,
+    new_password: String,
+    db: State<'_, DbState>,
+) -> Result<(), AppError> {
+    let invalid = || AppError::unauthorized("Invalid or expired reset code");
+    let username = normalize_username(&username);
+    // Wrong codes count towards the same lockout as wrong passwords
+    check_lockout(&db.db, &username).await?;
+
+    let users: Collection<User> = db.db.collection("users");
+    let resets: Collection<PasswordReset> = db.db.collection("password_resets");
+    let user = users.find_one(doc! { "username": &username }, None).await?;
+    let reset = match user.as_ref().and_then(|u| u.id) {
+        Some(user_id) => {
+            resets
+                .find_one(
+                    doc! {
+                        "user_id": user_id.to_hex(),
+                        "used": false,
+                        "expires_at": { "$gt": DateTime::now().timestamp_millis() }
+                    },
+                    None,
+                )
+                .await?
+        }
+        None => None,
+    };
+    let (user, reset) = match (user, reset) {
+        (Some(user), Some(reset)) if verify(code.trim(), &reset.code_hash).unwrap_or(false) => (user, reset),
+        (user, reset) => {
+            // Check a dummy code when there is nothing to check, so an unknown username
+            // or a missing code takes as long to reject as a wrong code
+            if reset.is_none() {
+                let cost = match &user {
+                    Some(user) => load_password_policy(&db.db, &user.store()).await?.bcrypt_cost,
+                    None => highest_bcrypt_cost(&db.db).await?,
+                };
+                let _ = verify(code.trim(), &dummy_hash(cost));
+            }
+            record_failure(&db.db, &username).await?;
+            return Err(invalid());
+        }
+    };
+
+    let policy = load_password_policy(&db.db, &user.store()).await?;
+    policy
+        .check(&new_password)
+        .map_err(|message| AppError::validation("new_password", message))?;
+
+    // Claim the code atomically so it cannot be used twice
+    let claimed = resets
+        .update_one(doc! { "_id": reset.id, "used": false }, doc! { "$set": { "used": true } }, None)
+        .await?;
+    if claimed.modified_count == 0 {
+        return Err(invalid());
+    }
+
+    set_password(&users, &user, policy.hash(&new_password)?).await?;
+    clear_failures(&db.db, &username).await
+}
+
+// After a successful login, re-hash the password if the store's bcrypt cost has been raised
+pub async fn upgrade_hash_if_needed(db: &Database, user: &User, password: &str) -> Result<(), AppError> {
+    let policy = load_password_policy(db, &user.store()).await?;
+    if hash_cost(&user.password_hash).is_some_and(|cost| cost >= policy.bcrypt_cost) {
+        return Ok(());
+    }
+    let users: Collection<User> = db.collection("users");
+    set_password(&users, user, policy.hash(password)?).await
+}
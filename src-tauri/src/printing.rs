@@ -0,0 +1,551 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::options::ReplaceOptions;
+use mongodb::Collection;
+use printpdf::{
+    image_crate, BuiltinFont, Image, ImageTransform, IndirectFontRef, Mm, PdfDocument, PdfDocumentReference,
+    PdfLayerReference,
+};
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::billing::{find_bill, format_local_date, round2, Bill};
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::session::{require_operator, require_role, SessionState};
+use crate::settings::{load_store_settings, StoreSettings};
+use crate::user::PRIVILEGED_ROLES;
+
+// ESC/POS control sequences understood by common 80mm/58mm thermal printers
+const ESC_INIT: &[u8] = &[0x1B, 0x40];
+const ESC_ALIGN_LEFT: &[u8] = &[0x1B, 0x61, 0x00];
+const ESC_ALIGN_CENTER: &[u8] = &[0x1B, 0x61, 0x01];
+const ESC_BOLD_ON: &[u8] = &[0x1B, 0x45, 0x01];
+const ESC_BOLD_OFF: &[u8] = &[0x1B, 0x45, 0x00];
+const GS_DOUBLE_SIZE: &[u8] = &[0x1D, 0x21, 0x11];
+const GS_NORMAL_SIZE: &[u8] = &[0x1D, 0x21, 0x00];
+const ESC_FEED_4: &[u8] = &[0x1B, 0x64, 0x04];
+const GS_PARTIAL_CUT: &[u8] = &[0x1D, 0x56, 0x42, 0x00];
+const ESC_DRAWER_KICK: &[u8] = &[0x1B, 0x70, 0x00, 0x19, 0xFA];
+
+const PDF_MARGIN: f32 = 12.0;
+const PDF_LOGO_WIDTH: f32 = 22.0;
+const PDF_CHAR_WIDTH: f32 = 0.2; // Approximate average Helvetica glyph width in mm per point of font size
+
+// How invoices are laid out for one store. The header and footer come from the
+// store settings unless the template gives its own lines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvoiceTemplate {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub paper_size: String, // "a4" or "a5" for PDF invoices
+    pub receipt_width: u32, // Characters per line: 48 for 80mm paper, 32 for 58mm
+    #[serde(default)]
+    pub header_lines: Vec<String>, // Store name first, then address, phone, licence numbers
+    #[serde(default)]
+    pub footer_lines: Vec<String>,
+    #[serde(default)]
+    pub show_logo: bool, // Print the store logo on PDF invoices
+    pub show_tax_breakdown: bool,
+    pub cut_paper: bool,
+    pub open_cash_drawer: bool,
+}
+
+impl InvoiceTemplate {
+    fn default_for(user_id: &str) -> Self {
+        InvoiceTemplate {
+            id: None,
+            user_id: user_id.to_string(),
+            paper_size: "a4".to_string(),
+            receipt_width: 48,
+            header_lines: Vec::new(),
+            footer_lines: Vec::new(),
+            show_logo: true,
+            show_tax_breakdown: true,
+            cut_paper: true,
+            open_cash_drawer: false,
+        }
+    }
+
+    fn header(&self, settings: &StoreSettings) -> Vec<String> {
+        if self.header_lines.is_empty() {
+            settings.header_lines()
+        } else {
+            self.header_lines.clone()
+        }
+    }
+
+    fn footer(&self, settings: &StoreSettings) -> Vec<String> {
+        if self.footer_lines.is_empty() {
+            settings.footer_lines()
+        } else {
+            self.footer_lines.clone()
+        }
+    }
+}
+
+// Taxable value and tax of the bill's lines at one tax rate
+struct TaxLine {
+    rate: f64,
+    taxable: f64,
+    tax: f64,
+}
+
+fn tax_breakdown(bill: &Bill) -> Vec<TaxLine> {
+    // Rates are keyed in hundredths of a percent so they can be ordered and grouped exactly
+    let mut rates: BTreeMap<i64, TaxLine> = BTreeMap::new();
+    for item in &bill.items {
+        let line = rates.entry((item.tax_rate * 100.0).round() as i64).or_insert(TaxLine {
+            rate: item.tax_rate,
+            taxable: 0.0,
+            tax: 0.0,
+        });
+        line.taxable += item.amount - item.tax_amount;
+        line.tax += item.tax_amount;
+    }
+    rates.into_values().collect()
+}
+
+fn payment_summary(bill: &Bill) -> Vec<(String, f64)> {
+    let mut lines: Vec<(String, f64)> = bill
+        .payments
+        .iter()
+        .map(|p| (format!("{:?}", p.mode).to_uppercase(), p.amount))
+        .collect();
+    if bill.change_due > 0.0 {
+        lines.push(("CHANGE".to_string(), bill.change_due));
+    }
+    lines
+}
+
+pub async fn load_template(db: &DbState, user_id: &str) -> Result<InvoiceTemplate, AppError> {
+    let templates: Collection<InvoiceTemplate> = db.db.collection("invoice_templates");
+    let template = templates.find_one(doc! { "user_id": user_id }, None).await?;
+    Ok(template.unwrap_or_else(|| InvoiceTemplate::default_for(user_id)))
+}
+
+fn pdf_error(error: printpdf::Error) -> AppError {
+    log::error!("Failed to build a PDF invoice: {}", error);
+    AppError::internal("Failed to build the PDF invoice")
+}
+
+// Keeps track of the write position while laying out PDF pages
+struct PdfCanvas {
+    document: PdfDocumentReference,
+    layer: PdfLayerReference,
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+    width: f32,
+    height: f32,
+    y: f32,
+}
+
+impl PdfCanvas {
+    fn new(title: &str, width: f32, height: f32) -> Result<Self, AppError> {
+        let (document, page, layer) = PdfDocument::new(title, Mm(width), Mm(height), "Layer 1");
+        let regular = document.add_builtin_font(BuiltinFont::Helvetica).map_err(pdf_error)?;
+        let bold = document.add_builtin_font(BuiltinFont::HelveticaBold).map_err(pdf_error)?;
+        let layer = document.get_page(page).get_layer(layer);
+        Ok(PdfCanvas { document, layer, regular, bold, width, height, y: height - PDF_MARGIN })
+    }
+
+    fn ensure_space(&mut self, needed: f32) {
+        if self.y - needed < PDF_MARGIN {
+            let (page, layer) = self.document.add_page(Mm(self.width), Mm(self.height), "Layer 1");
+            self.layer = self.document.get_page(page).get_layer(layer);
+            self.y = self.height - PDF_MARGIN;
+        }
+    }
+
+    fn text(&self, text: &str, x: f32, size: f32, bold: bool) {
+        let font = if bold { &self.bold } else { &self.regular };
+        self.layer.use_text(text, size, Mm(x), Mm(self.y), font);
+    }
+
+    fn text_right(&self, text: &str, right: f32, size: f32, bold: bool) {
+        let width = text.chars().count() as f32 * size * PDF_CHAR_WIDTH;
+        self.text(text, right - width, size, bold);
+    }
+
+    fn text_centered(&self, text: &str, size: f32, bold: bool) {
+        let width = text.chars().count() as f32 * size * PDF_CHAR_WIDTH;
+        self.text(text, ((self.width - width) / 2.0).max(PDF_MARGIN), size, bold);
+    }
+
+    // Draw an image in the top-left corner scaled to `width` mm. An unreadable
+    // logo is left out rather than stopping the invoice from printing.
+    fn logo(&self, path: &str, width: f32) {
+        let Ok(image) = image_crate::open(path) else {
+            return;
+        };
+        let (pixels_wide, pixels_high) = (image.width() as f32, image.height() as f32);
+        if pixels_wide == 0.0 {
+            return;
+        }
+        let dpi = pixels_wide * 25.4 / width;
+        let height = pixels_high * 25.4 / dpi;
+        Image::from_dynamic_image(&image).add_to_layer(
+            self.layer.clone(),
+            ImageTransform {
+                translate_x: Some(Mm(PDF_MARGIN)),
+                translate_y: Some(Mm(self.height - PDF_MARGIN - height)),
+                dpi: Some(dpi),
+                ..Default::default()
+            },
+        );
+    }
+
+    fn advance(&mut self, line_height: f32) {
+        self.y -= line_height;
+    }
+}
+
+fn render_pdf(
+    bill: &Bill,
+    settings: &StoreSettings,
+    template: &InvoiceTemplate,
+    paper_size: &str,
+) -> Result<Vec<u8>, AppError> {
+    let (width, height, size) = match paper_size {
+        "a4" => (210.0, 297.0, 10.0),
+        "a5" => (148.0, 210.0, 8.0),
+        other => {
+            return Err(AppError::validation("paper_size", format!("Unknown paper size '{}', expected a4 or a5", other)))
+        }
+    };
+    let line = size * 0.5;
+    let right = width - PDF_MARGIN;
+    let mut canvas = PdfCanvas::new(&format!("Invoice {}", bill.bill_number), width, height)?;
+    if template.show_logo {
+        if let Some(path) = &settings.logo_path {
+            canvas.logo(path, PDF_LOGO_WIDTH);
+        }
+    }
+
+    for (index, header) in template.header(settings).iter().enumerate() {
+        let (header_size, bold) = if index == 0 { (size + 6.0, true) } else { (size, false) };
+        canvas.text_centered(header, header_size, bold);
+        canvas.advance(if index == 0 { line * 1.6 } else { line });
+    }
+    canvas.advance(line);
+    let title = if bill.reprint_count > 0 { "TAX INVOICE (DUPLICATE)" } else { "TAX INVOICE" };
+    canvas.text_centered(title, size + 2.0, true);
+    canvas.advance(line * 1.5);
+
+    canvas.text(&format!("Bill No: {}", bill.bill_number), PDF_MARGIN, size, false);
+    canvas.text_right(&format!("Date: {}", format_local_date(bill.created_at)), right, size, false);
+    canvas.advance(line);
+    canvas.text(&format!("Customer: {}", bill.customer_name), PDF_MARGIN, size, false);
+    canvas.advance(line);
+    if let Some(patient) = &bill.patient_name {
+        canvas.text(&format!("Patient: {}", patient), PDF_MARGIN, size, false);
+        canvas.advance(line);
+    }
+    if let Some(billed_by) = &bill.billed_by {
+        canvas.text(&format!("Billed by: {}", billed_by), PDF_MARGIN, size, false);
+        canvas.advance(line);
+    }
+    canvas.advance(line);
+
+    // Item table; column positions are fractions of the printable width
+    let usable = width - 2.0 * PDF_MARGIN;
+    let columns = [0.0, 0.40, 0.56, 0.66, 0.80];
+    let x = |fraction: f32| PDF_MARGIN + usable * fraction;
+    let draw_heading = |canvas: &PdfCanvas| {
+        canvas.text("Item", x(columns[0]), size, true);
+        canvas.text("Batch", x(columns[1]), size, true);
+        canvas.text("Qty", x(columns[2]), size, true);
+        canvas.text("Rate", x(columns[3]), size, true);
+        canvas.text("Tax %", x(columns[4]), size, true);
+        canvas.text_right("Amount", right, size, true);
+    };
+    draw_heading(&canvas);
+    canvas.advance(line * 1.2);
+    let name_chars = ((columns[1] - columns[0]) * usable / (size * PDF_CHAR_WIDTH)) as usize;
+    for item in &bill.items {
+        if canvas.y - line < PDF_MARGIN {
+            canvas.ensure_space(line);
+            draw_heading(&canvas);
+            canvas.advance(line * 1.2);
+        }
+        let name: String = item.name.chars().take(name_chars.saturating_sub(1)).collect();
+        canvas.text(&name, x(columns[0]), size, false);
+        canvas.text(&item.batch_number, x(columns[1]), size, false);
+        canvas.text(&item.quantity.to_string(), x(columns[2]), size, false);
+        canvas.text(&format!("{:.2}", item.unit_price), x(columns[3]), size, false);
+        canvas.text(&format!("{:.2}", item.tax_rate), x(columns[4]), size, false);
+        canvas.text_right(&format!("{:.2}", item.amount), right, size, false);
+        canvas.advance(line);
+    }
+    canvas.advance(line);
+
+    canvas.ensure_space(line * 4.0);
+    for (label, amount, bold) in [
+        ("Subtotal", bill.subtotal, false),
+        ("Tax", bill.tax_total, false),
+        ("Round off", bill.round_off, false),
+        ("Total", bill.total, true),
+    ] {
+        if label == "Round off" && amount == 0.0 {
+            continue;
+        }
+        canvas.text(label, x(columns[3]), size, bold);
+        canvas.text_right(&format!("{:.2}", amount), right, size, bold);
+        canvas.advance(line);
+    }
+
+    if template.show_tax_breakdown && bill.tax_total > 0.0 {
+        canvas.advance(line);
+        canvas.ensure_space(line * 2.0);
+        canvas.text("Tax breakdown", PDF_MARGIN, size, true);
+        canvas.advance(line);
+        for tax in tax_breakdown(bill) {
+            canvas.ensure_space(line);
+            // GST is split equally between the central and state components
+            canvas.text(
+                &format!(
+                    "{:.2}%  on {:.2}:  CGST {:.2}  SGST {:.2}",
+                    tax.rate,
+                    round2(tax.taxable),
+                    round2(tax.tax / 2.0),
+                    round2(tax.tax - round2(tax.tax / 2.0))
+                ),
+                PDF_MARGIN,
+                size,
+                false,
+            );
+            canvas.advance(line);
+        }
+    }
+
+    canvas.advance(line);
+    for (mode, amount) in payment_summary(bill) {
+        canvas.ensure_space(line);
+        canvas.text(&format!("{}: {:.2}", mode, amount), PDF_MARGIN, size, false);
+        canvas.advance(line);
+    }
+
+    canvas.advance(line);
+    for footer in &template.footer(settings) {
+        canvas.ensure_space(line);
+        canvas.text_centered(footer, size - 1.0, false);
+        canvas.advance(line);
+    }
+
+    canvas.document.save_to_bytes().map_err(pdf_error)
+}
+
+// Thermal printers only have a basic code page, so anything outside ASCII is replaced
+fn printable(text: &str) -> String {
+    text.replace('₹', "Rs.")
+        .chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '?' })
+        .collect()
+}
+
+fn spread(left: &str, right: &str, width: usize) -> String {
+    let right_len = right.chars().count();
+    let left: String = left.chars().take(width.saturating_sub(right_len + 1)).collect();
+    let gap = width.saturating_sub(left.chars().count() + right_len);
+    format!("{}{}{}\n", left, " ".repeat(gap), right)
+}
+
+fn render_escpos(bill: &Bill, settings: &StoreSettings, template: &InvoiceTemplate) -> Vec<u8> {
+    let width = template.receipt_width.max(24) as usize;
+    let rule = format!("{}\n", "-".repeat(width));
+    let mut out: Vec<u8> = Vec::new();
+    let mut push = |bytes: &[u8]| out.extend_from_slice(bytes);
+
+    push(ESC_INIT);
+    push(ESC_ALIGN_CENTER);
+    for (index, header) in template.header(settings).iter().enumerate() {
+        if index == 0 {
+            push(ESC_BOLD_ON);
+            push(GS_DOUBLE_SIZE);
+        }
+        push(format!("{}\n", printable(header)).as_bytes());
+        if index == 0 {
+            push(GS_NORMAL_SIZE);
+            push(ESC_BOLD_OFF);
+        }
+    }
+    if bill.reprint_count > 0 {
+        push(b"DUPLICATE\n");
+    }
+    push(ESC_ALIGN_LEFT);
+    push(rule.as_bytes());
+    push(spread(&format!("Bill: {}", bill.bill_number), &format_local_date(bill.created_at), width).as_bytes());
+    if !bill.customer_name.is_empty() {
+        push(format!("Customer: {}\n", printable(&bill.customer_name)).as_bytes());
+    }
+    if let Some(patient) = &bill.patient_name {
+        push(format!("Patient: {}\n", printable(patient)).as_bytes());
+    }
+    if let Some(billed_by) = &bill.billed_by {
+        push(format!("Billed by: {}\n", printable(billed_by)).as_bytes());
+    }
+    push(rule.as_bytes());
+
+    for item in &bill.items {
+        push(format!("{}\n", printable(&item.name)).as_bytes());
+        let detail = format!("  {} x {:.2} ({}%)", item.quantity, item.unit_price, item.tax_rate);
+        push(spread(&detail, &format!("{:.2}", item.amount), width).as_bytes());
+    }
+    push(rule.as_bytes());
+
+    push(spread("Subtotal", &format!("{:.2}", bill.subtotal), width).as_bytes());
+    push(spread("Tax", &format!("{:.2}", bill.tax_total), width).as_bytes());
+    if bill.round_off != 0.0 {
+        push(spread("Round off", &format!("{:.2}", bill.round_off), width).as_bytes());
+    }
+    push(ESC_BOLD_ON);
+    push(spread("TOTAL", &format!("{:.2}", bill.total), width).as_bytes());
+    push(ESC_BOLD_OFF);
+
+    if template.show_tax_breakdown && bill.tax_total > 0.0 {
+        push(rule.as_bytes());
+        for tax in tax_breakdown(bill) {
+            let label = format!("GST {:.2}% on {:.2}", tax.rate, round2(tax.taxable));
+            push(spread(&label, &format!("{:.2}", round2(tax.tax)), width).as_bytes());
+        }
+    }
+
+    push(rule.as_bytes());
+    for (mode, amount) in payment_summary(bill) {
+        push(spread(&mode, &format!("{:.2}", amount), width).as_bytes());
+    }
+
+    push(ESC_ALIGN_CENTER);
+    for footer in &template.footer(settings) {
+        push(format!("{}\n", printable(footer)).as_bytes());
+    }
+    push(ESC_FEED_4);
+    if template.cut_paper {
+        push(GS_PARTIAL_CUT);
+    }
+    if template.open_cash_drawer {
+        push(ESC_DRAWER_KICK);
+    }
+    out
+}
+
+// Retrieve the invoice template for a store, falling back to the defaults
+#[command]
+pub async fn get_invoice_template(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<InvoiceTemplate, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    load_template(&db, &user_id).await
+}
+
+// Create or replace the invoice template for a store
+#[command]
+pub async fn update_invoice_template(
+    user_id: String,
+    template: InvoiceTemplate,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<InvoiceTemplate, AppError> {
+    let denied = "Only an owner or manager can change the invoice template";
+    require_role(&db.db, &session, &user_id, &PRIVILEGED_ROLES, denied).await?;
+    if !matches!(template.paper_size.as_str(), "a4" | "a5") {
+        return Err(AppError::validation("paper_size", "Paper size must be a4 or a5"));
+    }
+    if !(24..=64).contains(&template.receipt_width) {
+        return Err(AppError::validation("receipt_width", "Receipt width must be between 24 and 64 characters"));
+    }
+
+    let lines = |lines: Vec<String>| -> Vec<String> {
+        lines.into_iter().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect()
+    };
+    let template = InvoiceTemplate {
+        id: None,
+        user_id: user_id.clone(),
+        header_lines: lines(template.header_lines),
+        footer_lines: lines(template.footer_lines),
+        ..template
+    };
+    let templates: Collection<InvoiceTemplate> = db.db.collection("invoice_templates");
+    templates
+        .replace_one(
+            doc! { "user_id": &user_id },
+            &template,
+            ReplaceOptions::builder().upsert(true).build(),
+        )
+        .await?;
+
+    load_template(&db, &user_id).await
+}
+
+// Render a stored bill as an A4 or A5 PDF invoice at `path`
+#[command]
+pub async fn render_invoice_pdf(
+    user_id: String,
+    bill_id: String,
+    path: String,
+    paper_size: Option<String>,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<String, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let bill = find_bill(&db.db, &user_id, &bill_id).await?;
+    let settings = load_store_settings(&db.db, &user_id).await?;
+    let template = load_template(&db, &user_id).await?;
+    let paper_size = paper_size.unwrap_or_else(|| template.paper_size.clone());
+
+    let bytes = render_pdf(&bill, &settings, &template, &paper_size)?;
+    tokio::fs::write(&path, bytes).await.map_err(|e| {
+        log::warn!("Failed to write {}: {}", path, e);
+        AppError::validation("path", format!("Failed to write {}", path))
+    })?;
+
+    Ok(path)
+}
+
+// Render a stored bill as ESC/POS bytes and, when a printer is given, send them to it.
+// `printer` is either a network printer address such as "192.168.1.50:9100" or a device path.
+#[command]
+pub async fn print_receipt(
+    user_id: String,
+    bill_id: String,
+    printer: Option<String>,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<u8>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let bill = find_bill(&db.db, &user_id, &bill_id).await?;
+    let settings = load_store_settings(&db.db, &user_id).await?;
+    let template = load_template(&db, &user_id).await?;
+    let bytes = render_escpos(&bill, &settings, &template);
+
+    if let Some(printer) = printer {
+        let payload = bytes.clone();
+        tokio::task::spawn_blocking(move || -> Result<(), AppError> {
+            let sent = match printer.parse::<SocketAddr>() {
+                Ok(address) => TcpStream::connect_timeout(&address, Duration::from_secs(5))
+                    .and_then(|mut stream| stream.write_all(&payload)),
+                Err(_) => std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&printer)
+                    .and_then(|mut device| device.write_all(&payload)),
+            };
+            sent.map_err(|e| {
+                log::warn!("Failed to print to {}: {}", printer, e);
+                AppError::unavailable(format!("Printer {} cannot be reached", printer))
+            })
+        })
+        .await
+        .map_err(|e| {
+            log::error!("Printing task failed: {}", e);
+            AppError::internal("Failed to print the receipt")
+        })??;
+    }
+
+    Ok(bytes)
+}
+
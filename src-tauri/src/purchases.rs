@@ -0,0 +1,377 @@
+use std::collections::BTreeMap;
+use chrono::{Local, NaiveDate};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use mongodb::options::FindOptions;
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::billing::{round2, PaymentMode};
+use crate::commands::Medicine;
+use crate::db::DbState;
+use crate::error::{is_duplicate_key, AppError};
+use crate::session::{require_operator, SessionState};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PurchaseInvoiceLine {
+    pub medicine_id: String, // Batch received against this invoice
+    pub name: String,
+    pub batch_number: String,
+    pub quantity: u32,
+    pub unit_cost: f64,
+    pub tax_rate: f64,
+    pub tax_amount: f64,
+    pub amount: f64, // Line total including tax
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PurchaseInvoice {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub supplier_name: String,
+    pub invoice_number: String, // As printed by the supplier
+    pub invoice_date: String,
+    pub due_date: String,
+    pub lines: Vec<PurchaseInvoiceLine>,
+    pub subtotal: f64,
+    pub tax_total: f64,
+    pub total: f64,
+    pub amount_paid: f64,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurchaseLineInput {
+    pub medicine_id: String,
+    pub quantity: Option<u32>,   // Defaults to the batch's current quantity
+    pub unit_cost: Option<f64>,  // Defaults to the batch's purchase price
+    pub tax_rate: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SupplierPayment {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub invoice_id: String,
+    pub supplier_name: String,
+    pub amount: f64,
+    pub mode: PaymentMode,
+    pub reference: Option<String>,
+    pub paid_on: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutstandingInvoice {
+    pub invoice_id: String,
+    pub invoice_number: String,
+    pub invoice_date: String,
+    pub due_date: String,
+    pub total: f64,
+    pub amount_paid: f64,
+    pub outstanding: f64,
+    pub days_overdue: i64, // Negative while the invoice is not yet due
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SupplierPayables {
+    pub supplier_name: String,
+    pub outstanding: f64,
+    pub overdue: f64,
+    pub invoices: Vec<OutstandingInvoice>, // Ordered by due date
+}
+
+// `label` names the date in the message, e.g. "due date", and in snake case the field
+fn parse_date(value: &str, label: &str) -> Result<NaiveDate, AppError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        AppError::validation(&label.replace(' ', "_"), format!("Invalid {} '{}', expected YYYY-MM-DD", label, value))
+    })
+}
+
+// Check a supplier's invoice and price its lines against the store's batches without
+// saving it. Both entering an invoice and importing invoices from a file go through here.
+pub async fn prepare_purchase_invoice(
+    db: &Database,
+    user_id: &str,
+    supplier_name: &str,
+    invoice_number: &str,
+    invoice_date: String,
+    due_date: String,
+    lines: Vec<PurchaseLineInput>,
+) -> Result<PurchaseInvoice, AppError> {
+    let supplier_name = supplier_name.trim().to_string();
+    let invoice_number = invoice_number.trim().to_string();
+    if supplier_name.is_empty() || invoice_number.is_empty() {
+        return Err(AppError::validation("invoice_number", "Supplier name and invoice number are required"));
+    }
+    let due = parse_date(&due_date, "due date")?;
+    let invoiced = parse_date(&invoice_date, "invoice date")?;
+    if due < invoiced {
+        return Err(AppError::validation("due_date", "Due date cannot be before the invoice date"));
+    }
+    if lines.is_empty() {
+        return Err(AppError::validation("lines", "An invoice needs at least one line"));
+    }
+
+    let invoices: Collection<PurchaseInvoice> = db.collection("purchase_invoices");
+    let existing = invoices
+        .find_one(
+            doc! { "user_id": user_id, "supplier_name": &supplier_name, "invoice_number": &invoice_number },
+            None,
+        )
+        .await?;
+    if existing.is_some() {
+        return Err(already_recorded(&invoice_number, &supplier_name));
+    }
+
+    let medicines: Collection<Medicine> = db.collection("medicines");
+    let mut invoice_lines = Vec::new();
+    for line in lines {
+        let object_id = ObjectId::parse_str(&line.medicine_id)?;
+        let medicine = medicines
+            .find_one(doc! { "_id": object_id, "user_id": user_id, "deleted_at": null }, None)
+            .await?
+            .ok_or_else(|| AppError::not_found(format!("No medicine found with ID {}", line.medicine_id)))?;
+
+        let quantity = line.quantity.unwrap_or(medicine.quantity);
+        let unit_cost = line.unit_cost.unwrap_or(medicine.purchase_price);
+        let tax_rate = line.tax_rate.unwrap_or(0.0);
+        if quantity == 0 || !unit_cost.is_finite() || unit_cost < 0.0 || !tax_rate.is_finite() || tax_rate < 0.0 {
+            return Err(AppError::validation(
+                "lines",
+                format!("Invalid quantity, cost or tax for batch {}", medicine.batch_number),
+            ));
+        }
+
+        let net = round2(unit_cost * quantity as f64);
+        let tax_amount = round2(net * tax_rate / 100.0);
+        invoice_lines.push(PurchaseInvoiceLine {
+            medicine_id: line.medicine_id,
+            name: medicine.name,
+            batch_number: medicine.batch_number,
+            quantity,
+            unit_cost,
+            tax_rate,
+            tax_amount,
+            amount: round2(net + tax_amount),
+        });
+    }
+
+    let subtotal = round2(invoice_lines.iter().map(|l| l.amount - l.tax_amount).sum());
+    let tax_total = round2(invoice_lines.iter().map(|l| l.tax_amount).sum());
+    Ok(PurchaseInvoice {
+        id: None,
+        user_id: user_id.to_string(),
+        supplier_name,
+        invoice_number,
+        invoice_date,
+        due_date,
+        lines: invoice_lines,
+        subtotal,
+        tax_total,
+        total: round2(subtotal + tax_total),
+        amount_paid: 0.0,
+        created_at: DateTime::now().timestamp_millis(),
+    })
+}
+
+fn already_recorded(invoice_number: &str, supplier_name: &str) -> AppError {
+    AppError::conflict(format!("Invoice {} from {} has already been recorded", invoice_number, supplier_name))
+}
+
+pub async fn save_purchase_invoice(db: &Database, mut invoice: PurchaseInvoice) -> Result<PurchaseInvoice, AppError> {
+    let invoices: Collection<PurchaseInvoice> = db.collection("purchase_invoices");
+    // The unique index catches an invoice recorded between the check and this insert
+    let result = invoices.insert_one(&invoice, None).await.map_err(|e| {
+        if is_duplicate_key(&e) {
+            already_recorded(&invoice.invoice_number, &invoice.supplier_name)
+        } else {
+            AppError::from(e)
+        }
+    })?;
+    invoice.id = result.inserted_id.as_object_id();
+    Ok(invoice)
+}
+
+// Record a supplier's invoice against batches that have already been received
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub async fn record_purchase_invoice(
+    user_id: String,
+    supplier_name: String,
+    invoice_number: String,
+    invoice_date: String,
+    due_date: String,
+    lines: Vec<PurchaseLineInput>,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<PurchaseInvoice, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let invoice = prepare_purchase_invoice(
+        &db.db,
+        &user_id,
+        &supplier_name,
+        &invoice_number,
+        invoice_date,
+        due_date,
+        lines,
+    )
+    .await?;
+    save_purchase_invoice(&db.db, invoice).await
+}
+
+async fn load_purchase_invoices(
+    db: &Database,
+    user_id: &str,
+    supplier_name: Option<String>,
+    unpaid_only: Option<bool>,
+) -> Result<Vec<PurchaseInvoice>, AppError> {
+    let invoices: Collection<PurchaseInvoice> = db.collection("purchase_invoices");
+
+    let mut filter = doc! { "user_id": user_id };
+    if let Some(supplier_name) = supplier_name {
+        filter.insert("supplier_name", supplier_name);
+    }
+    if unpaid_only.unwrap_or(false) {
+        filter.insert("$expr", doc! { "$lt": ["$amount_paid", "$total"] });
+    }
+    let options = FindOptions::builder()
+        .sort(doc! { "due_date": 1, "invoice_date": 1 })
+        .build();
+
+    Ok(invoices.find(filter, options).await?.try_collect().await?)
+}
+
+// Retrieve purchase invoices, optionally for one supplier or only those still unpaid
+#[command]
+pub async fn get_purchase_invoices(
+    user_id: String,
+    supplier_name: Option<String>,
+    unpaid_only: Option<bool>,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<PurchaseInvoice>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    load_purchase_invoices(&db.db, &user_id, supplier_name, unpaid_only).await
+}
+
+// Record a payment made to a supplier against one of their invoices
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub async fn record_supplier_payment(
+    user_id: String,
+    invoice_id: String,
+    amount: f64,
+    mode: PaymentMode,
+    reference: Option<String>,
+    paid_on: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<PurchaseInvoice, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    if !amount.is_finite() || amount <= 0.0 {
+        return Err(AppError::validation("amount", "Payment amount must be greater than zero"));
+    }
+    if mode == PaymentMode::Credit {
+        return Err(AppError::validation("mode", "Supplier payments cannot be made on credit"));
+    }
+    parse_date(&paid_on, "payment date")?;
+    let amount = round2(amount);
+
+    let invoices: Collection<PurchaseInvoice> = db.db.collection("purchase_invoices");
+    let object_id = ObjectId::parse_str(&invoice_id)?;
+
+    // Refuse overpayment in the same update that records the payment
+    let filter = doc! {
+        "_id": object_id,
+        "user_id": &user_id,
+        "$expr": { "$lte": [ { "$add": ["$amount_paid", amount] }, { "$add": ["$total", 0.005] } ] }
+    };
+    let result = invoices
+        .update_one(filter, doc! { "$inc": { "amount_paid": amount } }, None)
+        .await?;
+
+    let invoice = invoices
+        .find_one(doc! { "_id": object_id, "user_id": &user_id }, None)
+        .await?
+        .ok_or_else(|| AppError::not_found("No purchase invoice found for the specified user and ID."))?;
+    if result.matched_count == 0 {
+        return Err(AppError::conflict(format!(
+            "Payment of {:.2} exceeds the outstanding amount of {:.2}",
+            amount,
+            round2(invoice.total - invoice.amount_paid)
+        )));
+    }
+
+    let payments: Collection<SupplierPayment> = db.db.collection("supplier_payments");
+    let payment = SupplierPayment {
+        id: None,
+        user_id,
+        invoice_id,
+        supplier_name: invoice.supplier_name.clone(),
+        amount,
+        mode,
+        reference,
+        paid_on,
+        created_at: DateTime::now().timestamp_millis(),
+    };
+    payments
+        .insert_one(payment, None)
+        .await?;
+
+    Ok(invoice)
+}
+
+// Outstanding payables grouped by supplier, with each supplier's invoices ordered by due date
+#[command]
+pub async fn get_payables_report(
+    user_id: String,
+    as_of: Option<String>,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<SupplierPayables>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let as_of = match as_of {
+        Some(date) => parse_date(&date, "report date")?,
+        None => Local::now().date_naive(),
+    };
+
+    let unpaid = load_purchase_invoices(&db.db, &user_id, None, Some(true)).await?;
+    let mut by_supplier: BTreeMap<String, SupplierPayables> = BTreeMap::new();
+    for invoice in unpaid {
+        let outstanding = round2(invoice.total - invoice.amount_paid);
+        if outstanding <= 0.0 {
+            continue;
+        }
+        let days_overdue = parse_date(&invoice.due_date, "due date")
+            .map(|due| (as_of - due).num_days())
+            .unwrap_or(0);
+
+        let entry = by_supplier
+            .entry(invoice.supplier_name.clone())
+            .or_insert_with(|| SupplierPayables {
+                supplier_name: invoice.supplier_name.clone(),
+                outstanding: 0.0,
+                overdue: 0.0,
+                invoices: Vec::new(),
+            });
+        entry.outstanding = round2(entry.outstanding + outstanding);
+        if days_overdue > 0 {
+            entry.overdue = round2(entry.overdue + outstanding);
+        }
+        entry.invoices.push(OutstandingInvoice {
+            invoice_id: invoice.id.map(|id| id.to_hex()).unwrap_or_default(),
+            invoice_number: invoice.invoice_number,
+            invoice_date: invoice.invoice_date,
+            due_date: invoice.due_date,
+            total: invoice.total,
+            amount_paid: invoice.amount_paid,
+            outstanding,
+            days_overdue,
+        });
+    }
+
+    Ok(by_supplier.into_values().collect())
+}
@@ -0,0 +1,208 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use bcrypt::verify;
+use mongodb::bson::doc;
+use mongodb::{Collection, Database};
+use serde::Serialize;
+use tauri::{command, State};
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::login_guard::{check_lockout, clear_failures, record_failure, record_login_event};
+use crate::model::User;
+use crate::password::load_password_policy;
+use crate::settings::load_store_settings;
+use crate::user::{find_user, normalize_username};
+
+// Who is working at this terminal right now. Bills are attributed to this user,
+// and the terminal locks itself after the store's idle timeout.
+#[derive(Debug, Default)]
+struct TerminalSession {
+    user_id: Option<String>,
+    username: Option<String>,
+    last_activity: Option<Instant>,
+    idle_timeout: Option<Duration>, // None never locks
+}
+
+#[derive(Debug, Default)]
+pub struct SessionState {
+    inner: Mutex<TerminalSession>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionStatus {
+    pub locked: bool,
+    pub user_id: Option<String>,
+    pub username: Option<String>,
+    pub seconds_until_lock: Option<u64>,
+}
+
+impl SessionState {
+    // Make `user` the active user of the terminal
+    pub fn start(&self, user: &User, idle_minutes: u32) {
+        let mut session = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        *session = TerminalSession {
+            user_id: user.id.map(|id| id.to_hex()),
+            username: Some(user.username.clone()),
+            last_activity: Some(Instant::now()),
+            idle_timeout: (idle_minutes > 0).then(|| Duration::from_secs(idle_minutes as u64 * 60)),
+        };
+    }
+
+    pub fn lock(&self) {
+        let mut session = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        *session = TerminalSession::default();
+    }
+
+    // The unlocked user, refreshing their idle timer. Fails once the terminal has locked.
+    pub fn active_user(&self) -> Result<String, AppError> {
+        let mut session = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let expired = match (session.last_activity, session.idle_timeout) {
+            (Some(last), Some(timeout)) => last.elapsed() >= timeout,
+            _ => false,
+        };
+        if expired {
+            *session = TerminalSession::default();
+        }
+        let user_id = session
+            .user_id
+            .clone()
+            .ok_or_else(|| AppError::unauthorized("Terminal is locked. Unlock with your PIN to continue."))?;
+        session.last_activity = Some(Instant::now());
+        Ok(user_id)
+    }
+
+    fn status(&self) -> SessionStatus {
+        let session = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let remaining = match (session.last_activity, session.idle_timeout) {
+            (Some(last), Some(timeout)) => Some(timeout.saturating_sub(last.elapsed())),
+            _ => None,
+        };
+        let locked = session.user_id.is_none() || remaining.is_some_and(|r| r.is_zero());
+        SessionStatus {
+            locked,
+            user_id: if locked { None } else { session.user_id.clone() },
+            username: if locked { None } else { session.username.clone() },
+            seconds_until_lock: if locked { None } else { remaining.map(|r| r.as_secs()) },
+        }
+    }
+}
+
+// Start a terminal session for a user who has just proved who they are
+pub async fn start_session(db: &DbState, session: &SessionState, user: &User) -> Result<(), AppError> {
+    // Staff lock after their store's idle timeout, not one of their own
+    let settings = load_store_settings(&db.db, &user.store()).await?;
+    session.start(user, settings.auto_lock_minutes);
+    Ok(())
+}
+
+// The user unlocked at this terminal, provided they work for `store_id`
+pub async fn require_operator(db: &Database, session: &SessionState, store_id: &str) -> Result<User, AppError> {
+    let operator_id = session.active_user()?;
+    let users: Collection<User> = db.collection("users");
+    let operator = find_user(&users, &operator_id).await?;
+    if operator.store() != store_id {
+        return Err(AppError::forbidden("You do not have access to this store"));
+    }
+    Ok(operator)
+}
+
+// Like `require_operator`, but the operator must also hold one of `roles`
+pub async fn require_role(
+    db: &Database,
+    session: &SessionState,
+    store_id: &str,
+    roles: &[&str],
+    denied: &str,
+) -> Result<User, AppError> {
+    let operator = require_operator(db, session, store_id).await?;
+    if !roles.contains(&operator.role.as_str()) {
+        return Err(AppError::forbidden(denied));
+    }
+    Ok(operator)
+}
+
+// Set or replace the unlocked user's own PIN; the account password confirms the change
+#[command]
+pub async fn set_pin(
+    user_id: String,
+    password: String,
+    pin: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<(), AppError> {
+    let users: Collection<User> = db.db.collection("users");
+    let user = require_operator(&db.db, &session, &user_id).await?;
+    if !verify(&password, &user.password_hash).unwrap_or(false) {
+        return Err(AppError::validation("password", "Password is incorrect"));
+    }
+    if !(4..=6).contains(&pin.len()) || !pin.chars().all(|c| c.is_ascii_digit()) {
+        return Err(AppError::validation("pin", "PIN must be 4 to 6 digits"));
+    }
+
+    // PINs are hashed at the store's password cost, like passwords
+    let pin_hash = load_password_policy(&db.db, &user.store()).await?.hash(&pin)?;
+    users
+        .update_one(doc! { "_id": user.id }, doc! { "$set": { "pin_hash": pin_hash } }, None)
+        .await?;
+    Ok(())
+}
+
+// Unlock the terminal, or switch it to another user, with that user's PIN.
+// Wrong PINs count towards the same lockout as wrong passwords.
+#[command]
+pub async fn unlock_with_pin(
+    username: String,
+    pin: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<String, AppError> {
+    let username = normalize_username(&username);
+    if let Err(e) = check_lockout(&db.db, &username).await {
+        record_login_event(&db.db, &username, None, "locked").await?;
+        return Err(e);
+    }
+
+    let users: Collection<User> = db.db.collection("users");
+    let user = users.find_one(doc! { "username": &username }, None).await?;
+    let matches = match user.as_ref().and_then(|u| u.pin_hash.as_deref()) {
+        Some(pin_hash) => verify(&pin, pin_hash).unwrap_or(false),
+        None => false,
+    };
+
+    match user {
+        Some(user) if matches => {
+            let user_id = user.id.ok_or_else(|| AppError::internal("Failed to retrieve user ID"))?.to_hex();
+            clear_failures(&db.db, &username).await?;
+            record_login_event(&db.db, &username, Some(user_id), "pin_unlock").await?;
+            start_session(&db, &session, &user).await?;
+            // The frontend keys every store command by the store, not the operator
+            Ok(user.store())
+        }
+        _ => {
+            record_failure(&db.db, &username).await?;
+            record_login_event(&db.db, &username, None, "failure").await?;
+            Err(AppError::unauthorized("Invalid username or PIN"))
+        }
+    }
+}
+
+// Lock the terminal straight away, e.g. when a cashier steps away
+#[command]
+pub async fn lock_terminal(session: State<'_, SessionState>) -> Result<(), AppError> {
+    session.lock();
+    Ok(())
+}
+
+// Reset the idle timer; the frontend calls this on user input
+#[command]
+pub async fn record_activity(session: State<'_, SessionState>) -> Result<SessionStatus, AppError> {
+    // A locked terminal stays locked, the status says so
+    let _ = session.active_user();
+    Ok(session.status())
+}
+
+// Whether the terminal is locked, who is active and how long until it locks
+#[command]
+pub async fn get_session_status(session: State<'_, SessionState>) -> Result<SessionStatus, AppError> {
+    Ok(session.status())
+}
@@ -0,0 +1,237 @@
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use mongodb::options::ReplaceOptions;
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::billing::round2;
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::session::{require_operator, require_role, SessionState};
+use crate::user::PRIVILEGED_ROLES;
+
+// Store identity and regulatory details printed on bills and reports,
+// plus the defaults billing applies when a sale does not say otherwise
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoreSettings {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: String,
+    pub legal_name: String,
+    pub address: String, // May span several lines
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    pub drug_licence_numbers: Vec<String>, // e.g. the Form 20 and Form 21 licences
+    pub gstin: Option<String>,
+    pub pharmacist_in_charge: Option<String>,
+    pub pharmacist_registration: Option<String>,
+    pub logo_path: Option<String>, // PNG or JPEG on the local disk
+    pub invoice_footer: String,
+    pub default_tax_rate: f64, // Percentage applied to bill items that do not give one
+    pub round_off_to: f64, // Bill totals are rounded to a multiple of this, 0 disables rounding
+    #[serde(default = "default_auto_lock_minutes")]
+    pub auto_lock_minutes: u32, // Idle time before the terminal locks, 0 never locks
+    #[serde(default)]
+    pub allow_selling_below_cost: bool, // Accept such batches with a warning instead of rejecting them
+    #[serde(default)]
+    pub max_selling_price: Option<f64>, // Upper bound on a batch's selling price (MRP)
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32, // How long deleted batches can be restored before they are purged
+    #[serde(default = "default_reorder_level")]
+    pub default_reorder_level: u32, // Stock at or below which a product needs reordering, 0 turns it off
+    #[serde(default)]
+    pub updated_at: i64,
+}
+
+impl StoreSettings {
+    pub fn default_for(user_id: &str) -> Self {
+        StoreSettings {
+            id: None,
+            user_id: user_id.to_string(),
+            legal_name: String::new(),
+            address: String::new(),
+            phone: None,
+            email: None,
+            drug_licence_numbers: Vec::new(),
+            gstin: None,
+            pharmacist_in_charge: None,
+            pharmacist_registration: None,
+            logo_path: None,
+            invoice_footer: "Thank you! Please retain this receipt for your records.".to_string(),
+            default_tax_rate: 0.0,
+            round_off_to: 0.0,
+            auto_lock_minutes: default_auto_lock_minutes(),
+            allow_selling_below_cost: false,
+            max_selling_price: None,
+            trash_retention_days: default_trash_retention_days(),
+            default_reorder_level: default_reorder_level(),
+            updated_at: 0,
+        }
+    }
+
+    // Round a bill total according to the store's rounding rule
+    pub fn round_total(&self, total: f64) -> f64 {
+        if self.round_off_to > 0.0 {
+            round2((total / self.round_off_to).round() * self.round_off_to)
+        } else {
+            round2(total)
+        }
+    }
+
+    // Lines identifying the store at the top of an invoice, name first
+    pub fn header_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if !self.legal_name.is_empty() {
+            lines.push(self.legal_name.clone());
+        }
+        lines.extend(self.address.lines().map(str::trim).filter(|l| !l.is_empty()).map(String::from));
+        let contact: Vec<&str> = [self.phone.as_deref(), self.email.as_deref()].into_iter().flatten().collect();
+        if !contact.is_empty() {
+            lines.push(contact.join("  "));
+        }
+        if !self.drug_licence_numbers.is_empty() {
+            lines.push(format!("D.L. No: {}", self.drug_licence_numbers.join(", ")));
+        }
+        if let Some(gstin) = &self.gstin {
+            lines.push(format!("GSTIN: {}", gstin));
+        }
+        lines
+    }
+
+    // Lines closing an invoice: the pharmacist in charge, then the store's footer
+    pub fn footer_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(pharmacist) = &self.pharmacist_in_charge {
+            match &self.pharmacist_registration {
+                Some(registration) => lines.push(format!("Pharmacist: {} (Reg. {})", pharmacist, registration)),
+                None => lines.push(format!("Pharmacist: {}", pharmacist)),
+            }
+        }
+        lines.extend(self.invoice_footer.lines().map(str::trim).filter(|l| !l.is_empty()).map(String::from));
+        lines
+    }
+}
+
+fn default_auto_lock_minutes() -> u32 {
+    10
+}
+
+fn default_trash_retention_days() -> u32 {
+    30
+}
+
+fn default_reorder_level() -> u32 {
+    10
+}
+
+pub async fn load_store_settings(db: &Database, user_id: &str) -> Result<StoreSettings, AppError> {
+    let collection: Collection<StoreSettings> = db.collection("store_settings");
+    let settings = collection.find_one(doc! { "user_id": user_id }, None).await?;
+    Ok(settings.unwrap_or_else(|| StoreSettings::default_for(user_id)))
+}
+
+fn trimmed(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+// A GSTIN is 15 characters: state code, PAN, entity number, 'Z' and a check character
+fn valid_gstin(gstin: &str) -> bool {
+    let chars: Vec<char> = gstin.chars().collect();
+    chars.len() == 15
+        && chars.iter().all(|c| c.is_ascii_digit() || c.is_ascii_uppercase())
+        && chars[0].is_ascii_digit()
+        && chars[1].is_ascii_digit()
+}
+
+// Retrieve the store settings, falling back to empty defaults before they are first saved
+#[command]
+pub async fn get_store_settings(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<StoreSettings, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    load_store_settings(&db.db, &user_id).await
+}
+
+// Create or replace the store settings. Only an owner or manager may change them.
+#[command]
+pub async fn update_store_settings(
+    user_id: String,
+    settings: StoreSettings,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<StoreSettings, AppError> {
+    let denied = "Only an owner or manager can change store settings";
+    require_role(&db.db, &session, &user_id, &PRIVILEGED_ROLES, denied).await?;
+
+    let legal_name = settings.legal_name.trim().to_string();
+    if legal_name.is_empty() {
+        return Err(AppError::validation("legal_name", "Store legal name is required"));
+    }
+    let gstin = trimmed(settings.gstin).map(|g| g.to_uppercase());
+    if let Some(gstin) = &gstin {
+        if !valid_gstin(gstin) {
+            return Err(AppError::validation("gstin", format!("'{}' is not a valid GSTIN", gstin)));
+        }
+    }
+    if !(0.0..=100.0).contains(&settings.default_tax_rate) {
+        return Err(AppError::validation("default_tax_rate", "Default tax rate must be between 0 and 100"));
+    }
+    if !(0.0..=10.0).contains(&settings.round_off_to) {
+        return Err(AppError::validation("round_off_to", "Round-off must be between 0 and 10"));
+    }
+    if settings.auto_lock_minutes > 240 {
+        return Err(AppError::validation("auto_lock_minutes", "Auto-lock must be at most 240 minutes"));
+    }
+    if settings.max_selling_price.is_some_and(|max| !max.is_finite() || max <= 0.0) {
+        return Err(AppError::validation("max_selling_price", "Maximum selling price must be a positive amount"));
+    }
+    if !(1..=365).contains(&settings.trash_retention_days) {
+        return Err(AppError::validation("trash_retention_days", "Trash retention must be between 1 and 365 days"));
+    }
+    let logo_path = trimmed(settings.logo_path);
+    if let Some(path) = &logo_path {
+        if !std::path::Path::new(path).is_file() {
+            return Err(AppError::validation("logo_path", format!("Logo file {} does not exist", path)));
+        }
+    }
+
+    let settings = StoreSettings {
+        id: None,
+        user_id: user_id.clone(),
+        legal_name,
+        address: settings.address.trim().to_string(),
+        phone: trimmed(settings.phone),
+        email: trimmed(settings.email),
+        drug_licence_numbers: settings
+            .drug_licence_numbers
+            .into_iter()
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty())
+            .collect(),
+        gstin,
+        pharmacist_in_charge: trimmed(settings.pharmacist_in_charge),
+        pharmacist_registration: trimmed(settings.pharmacist_registration),
+        logo_path,
+        invoice_footer: settings.invoice_footer.trim().to_string(),
+        default_tax_rate: settings.default_tax_rate,
+        round_off_to: settings.round_off_to,
+        auto_lock_minutes: settings.auto_lock_minutes,
+        allow_selling_below_cost: settings.allow_selling_below_cost,
+        max_selling_price: settings.max_selling_price,
+        trash_retention_days: settings.trash_retention_days,
+        default_reorder_level: settings.default_reorder_level,
+        updated_at: DateTime::now().timestamp_millis(),
+    };
+
+    let collection: Collection<StoreSettings> = db.db.collection("store_settings");
+    collection
+        .replace_one(
+            doc! { "user_id": &user_id },
+            &settings,
+            ReplaceOptions::builder().upsert(true).build(),
+        )
+        .await?;
+
+    load_store_settings(&db.db, &user_id).await
+}
@@ -0,0 +1,87 @@
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, to_document, Document};
+use mongodb::options::{FindOptions, UpdateOptions};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::session::{require_operator, SessionState};
+use crate::settings::StoreSettings;
+
+// Categories every new store starts with; staff can add their own on top
+const DEFAULT_CATEGORIES: [&str; 8] = [
+    "Tablet", "Capsule", "Syrup", "Injection", "Ointment", "Drops", "Inhaler", "Surgical",
+];
+
+// Per-store counters that must exist before the first document is numbered
+const COUNTERS: [&str; 1] = ["bill"];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Category {
+    pub user_id: String,
+    pub name: String,
+    pub created_at: i64,
+}
+
+// Make sure a store has everything it needs to start trading. Only creates what is
+// missing, so it is safe to call on every login.
+pub async fn bootstrap_tenant(db: &Database, user_id: &str) -> Result<(), AppError> {
+    let upsert = || UpdateOptions::builder().upsert(true).build();
+
+    let mut settings = to_document(&StoreSettings::default_for(user_id)).map_err(|e| {
+        log::error!("Failed to encode default store settings: {}", e);
+        AppError::internal("Failed to set up the store")
+    })?;
+    settings.remove("user_id");
+    settings.insert("updated_at", Utc::now().timestamp_millis());
+    let store_settings: Collection<Document> = db.collection("store_settings");
+    store_settings
+        .update_one(doc! { "user_id": user_id }, doc! { "$setOnInsert": settings }, upsert())
+        .await?;
+
+    let categories: Collection<Document> = db.collection("categories");
+    for name in DEFAULT_CATEGORIES {
+        categories
+            .update_one(
+                doc! { "user_id": user_id, "name": name },
+                doc! { "$setOnInsert": { "created_at": Utc::now().timestamp_millis() } },
+                upsert(),
+            )
+            .await?;
+    }
+
+    let counters: Collection<Document> = db.collection("counters");
+    for name in COUNTERS {
+        counters
+            .update_one(
+                doc! { "user_id": user_id, "name": name },
+                doc! { "$setOnInsert": { "value": 0_i64 } },
+                upsert(),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+// Categories a batch can be filed under, alphabetically
+#[command]
+pub async fn get_categories(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<String>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let categories: Collection<Category> = db.db.collection("categories");
+    let options = FindOptions::builder().sort(doc! { "name": 1 }).build();
+    let names = categories
+        .find(doc! { "user_id": &user_id }, options)
+        .await?
+        .try_collect::<Vec<Category>>()
+        .await?
+        .into_iter()
+        .map(|c| c.name)
+        .collect();
+    Ok(names)
+}
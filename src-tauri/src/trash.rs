@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+use chrono::{Duration, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use mongodb::{Collection, Database};
+use serde::Serialize;
+use tauri::{command, State};
+use crate::commands::Medicine;
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::ledger::{record_movements, StockMovement};
+use crate::session::{require_operator, require_role, SessionState};
+use crate::settings::load_store_settings;
+use crate::user::PRIVILEGED_ROLES;
+use crate::validation::duplicate_batch_error;
+
+#[derive(Serialize)]
+pub struct TrashedMedicine {
+    #[serde(flatten)]
+    pub medicine: Medicine,
+    pub purge_after: i64, // When the purge job may remove it for good
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct PurgeReport {
+    pub purged: u64,
+    pub kept_billed: Vec<String>, // Batches past retention that bills still refer to
+}
+
+// Epoch millis before which a trashed batch is past the store's retention period
+async fn retention_cutoff(db: &Database, user_id: &str) -> Result<(i64, i64), AppError> {
+    let settings = load_store_settings(db, user_id).await?;
+    let retention = Duration::days(settings.trash_retention_days as i64).num_milliseconds();
+    Ok((Utc::now().timestamp_millis() - retention, retention))
+}
+
+// List a store's deleted batches, most recently deleted first
+#[command]
+pub async fn get_deleted_medicines(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Vec<TrashedMedicine>, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let (_, retention) = retention_cutoff(&db.db, &user_id).await?;
+    let collection: Collection<Medicine> = db.db.collection("medicines");
+    let options = FindOptions::builder().sort(doc! { "deleted_at": -1 }).build();
+    let medicines: Vec<Medicine> = collection
+        .find(doc! { "user_id": &user_id, "deleted_at": { "$ne": null } }, options)
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(medicines
+        .into_iter()
+        .map(|medicine| TrashedMedicine {
+            purge_after: medicine.deleted_at.unwrap_or_default() + retention,
+            medicine,
+        })
+        .collect())
+}
+
+// Bring a deleted batch back into stock, as long as it is still within the retention period
+#[command]
+pub async fn restore_medicine(
+    user_id: String,
+    id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<Medicine, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    let (cutoff, _) = retention_cutoff(&db.db, &user_id).await?;
+    let collection: Collection<Medicine> = db.db.collection("medicines");
+    let object_id = ObjectId::parse_str(&id)?;
+
+    let trashed = collection
+        .find_one(doc! { "_id": object_id, "user_id": &user_id, "deleted_at": { "$ne": null } }, None)
+        .await?
+        .ok_or_else(|| AppError::not_found("No deleted medicine found for the specified user and ID."))?;
+    if trashed.deleted_at.is_some_and(|deleted_at| deleted_at < cutoff) {
+        return Err(AppError::conflict("This medicine is past the trash retention period and can no longer be restored"));
+    }
+
+    let update = doc! {
+        "$unset": { "deleted_at": "", "deleted_by": "" },
+        "$inc": { "version": 1_i64 }
+    };
+    let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+    let restored = collection
+        .find_one_and_update(doc! { "_id": object_id, "deleted_at": trashed.deleted_at }, update, options)
+        .await
+        .map_err(|e| match duplicate_batch_error(e, &trashed) {
+            AppError::Conflict { .. } => AppError::conflict(format!(
+                "Batch {} of {} has been entered again since it was deleted, so it cannot be restored",
+                trashed.batch_number, trashed.name
+            )),
+            other => other,
+        })?
+        .ok_or_else(|| AppError::conflict("This medicine was restored or purged by someone else"))?;
+
+    let movement = StockMovement::for_batch(&restored, restored.quantity as i64, "restore", None);
+    // The batch is back either way; a missing ledger entry is corrected by hand
+    if let Err(e) = record_movements(&db.db, vec![movement]).await {
+        log::error!("Batch {} was restored but its stock was not recorded: {}", restored.batch_number, e);
+    }
+    Ok(restored)
+}
+
+// Permanently remove a store's batches that have been in the trash longer than the
+// retention period. Batches that appear on a bill are kept so the bill can still be traced.
+pub async fn purge_expired_medicines(db: &Database, user_id: &str) -> Result<PurgeReport, AppError> {
+    let (cutoff, _) = retention_cutoff(db, user_id).await?;
+    let collection: Collection<Medicine> = db.collection("medicines");
+    let expired: Vec<Medicine> = collection
+        .find(doc! { "user_id": user_id, "deleted_at": { "$lt": cutoff } }, None)
+        .await?
+        .try_collect()
+        .await?;
+    if expired.is_empty() {
+        return Ok(PurgeReport::default());
+    }
+
+    let ids: Vec<String> = expired.iter().filter_map(|m| m.id.map(|id| id.to_hex())).collect();
+    let bills: Collection<Document> = db.collection("bills");
+    let billed: HashSet<String> = bills
+        .distinct("items.medicine_id", doc! { "user_id": user_id, "items.medicine_id": { "$in": &ids } }, None)
+        .await?
+        .into_iter()
+        .filter_map(|id| id.as_str().map(String::from))
+        .collect();
+
+    let mut report = PurgeReport::default();
+    let mut purgeable: Vec<ObjectId> = Vec::new();
+    for medicine in &expired {
+        let Some(id) = medicine.id else { continue };
+        if billed.contains(&id.to_hex()) {
+            report.kept_billed.push(format!("{} batch {}", medicine.name, medicine.batch_number));
+        } else {
+            purgeable.push(id);
+        }
+    }
+    if !purgeable.is_empty() {
+        // Match on `deleted_at` too, so a batch restored meanwhile is left alone
+        let filter = doc! { "_id": { "$in": purgeable }, "user_id": user_id, "deleted_at": { "$lt": cutoff } };
+        report.purged = collection.delete_many(filter, None).await?.deleted_count;
+    }
+    Ok(report)
+}
+
+// Purge the store's expired trash now. Only an owner or manager may do this.
+#[command]
+pub async fn purge_deleted_medicines(
+    user_id: String,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<PurgeReport, AppError> {
+    let denied = "Only an owner or manager can purge deleted medicines";
+    require_role(&db.db, &session, &user_id, &PRIVILEGED_ROLES, denied).await?;
+    purge_expired_medicines(&db.db, &user_id).await
+}
+
+// Run the purge for every store with something in the trash; called at startup
+pub async fn purge_all_expired(db: &Database) -> Result<(), AppError> {
+    let collection: Collection<Medicine> = db.collection("medicines");
+    let stores = collection
+        .distinct("user_id", doc! { "deleted_at": { "$ne": null } }, None)
+        .await?;
+    for user_id in stores.iter().filter_map(|id| id.as_str()) {
+        let report = purge_expired_medicines(db, user_id).await?;
+        if !report.kept_billed.is_empty() {
+            log::info!(
+                "Kept {} deleted batch(es) for store {} because bills refer to them",
+                report.kept_billed.len(),
+                user_id
+            );
+        }
+    }
+    Ok(())
+}
@@ -33,9 +33,14 @@
 
 
 use mongodb::bson::{doc, oid::ObjectId};
-use mongodb::Collection;
-use bcrypt::{hash, verify, DEFAULT_COST};
-use crate::model::User;
+use mongodb::options::IndexOptions;
+use mongodb::{Collection, Database, IndexModel};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use bcrypt::{hash, verify};
+use crate::error::{duplicate_key_message, AppError};
+use crate::model::{ProfileUpdate, User};
+use crate::password::{highest_bcrypt_cost, PasswordPolicy};
 
 // pub async fn signup_user(
 //     user_collection: &Collection<User>,
@@ -62,54 +67,222 @@ use crate::model::User;
 //     Ok(user)
 // }
 
+// Sign up the owner of a new store
 pub async fn signup_user(
     user_collection: &Collection<User>,
     username: &str,
     password: &str,
     email: &str,
-) -> Result<User, String> { // Return User on success
-    let password_hash = hash(password, DEFAULT_COST).map_err(|e| e.to_string())?;
+    policy: &PasswordPolicy,
+) -> Result<User, AppError> { // Return User on success
+    create_user(user_collection, username, password, email, "owner", None, policy).await
+}
+
+// Create an account with the given role. `store_id` is None for the owner of a new
+// store and the owner's ID for staff.
+pub async fn create_user(
+    user_collection: &Collection<User>,
+    username: &str,
+    password: &str,
+    email: &str,
+    role: &str,
+    store_id: Option<String>,
+    policy: &PasswordPolicy,
+) -> Result<User, AppError> {
+    let username = normalize_username(username);
+    let email = normalize_email(email);
+    validate_username(&username)?;
+    validate_email(&email)?;
+    policy.check(password).map_err(|message| AppError::validation("password", message))?;
+    let password_hash = policy.hash(password)?;
     
     // Create a new user without specifying `id`, MongoDB will generate `_id`
     let user = User {
         id: None,
-        username: username.to_string(),
+        username,
         password_hash,
-        email: email.to_string(),
+        email,
+        role: role.to_string(),
+        store_id,
+        display_name: None,
+        phone: None,
+        qualification: None,
+        registration_number: None,
+        avatar_path: None,
+        pin_hash: None,
     };
     
-    // Insert the user into the collection
-    user_collection.insert_one(&user, None)
+    // Insert the user into the collection; the unique indexes reject duplicates
+    let result = user_collection.insert_one(&user, None)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(duplicate_user_error)?;
     
-    // Retrieve the user with MongoDB's generated `_id`
-    let inserted_user = user_collection
-        .find_one(doc! { "email": email }, None)
-        .await
-        .map_err(|e| e.to_string())?
-        .ok_or("Failed to retrieve user after insertion".to_string())?;
-    
-    Ok(inserted_user)
+    // Return the user with MongoDB's generated `_id`
+    Ok(User { id: result.inserted_id.as_object_id(), ..user })
 }
 
 
 pub async fn login_user(
-    user_collection: &Collection<User>,
+    db: &Database,
     username: &str,
     password: &str,
-) -> Result<User, String> { // Return User on success
+) -> Result<User, AppError> { // Return User on success
     // Find the user document by username
-    let user_doc = user_collection.find_one(doc! { "username": username }, None)
-        .await
-        .map_err(|e| e.to_string())?;
+    let user_collection: Collection<User> = db.collection("users");
+    let user_doc = user_collection.find_one(doc! { "username": normalize_username(username) }, None)
+        .await?;
     
-    // Check if the user exists and if the password is correct
-    if let Some(user) = user_doc {
-        if verify(password, &user.password_hash).map_err(|e| e.to_string())? {
-            // Return the user if authentication is successful
-            return Ok(user);
+    // Verify against a dummy hash when the user is missing, so an unknown
+    // username takes as long to reject as a wrong password
+    let password_hash = match &user_doc {
+        Some(user) => user.password_hash.clone(),
+        None => dummy_hash(highest_bcrypt_cost(db).await?),
+    };
+    let matches = verify(password, &password_hash).unwrap_or(false);
+
+    match user_doc {
+        // Return the user if authentication is successful
+        Some(user) if matches => Ok(user),
+        _ => Err(AppError::unauthorized("Invalid username or password")),
+    }
+}
+
+// A hash of no real password at `cost`, made once per cost
+pub fn dummy_hash(cost: u32) -> String {
+    static DUMMY_HASHES: OnceLock<Mutex<HashMap<u32, String>>> = OnceLock::new();
+    let mut hashes = DUMMY_HASHES.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    hashes
+        .entry(cost)
+        .or_insert_with(|| hash("not a real password", cost).unwrap_or_default())
+        .clone()
+}
+
+
+// Roles allowed to override business limits such as customer credit
+pub const PRIVILEGED_ROLES: [&str; 2] = ["owner", "manager"];
+
+// Roles an owner can give staff. Only signing up makes an owner, and only of a new store.
+pub const STAFF_ROLES: [&str; 3] = ["manager", "pharmacist", "cashier"];
+
+pub async fn find_user(user_collection: &Collection<User>, user_id: &str) -> Result<User, AppError> {
+    let object_id = ObjectId::parse_str(user_id)?;
+    user_collection
+        .find_one(doc! { "_id": object_id }, None)
+        .await?
+        .ok_or_else(|| AppError::not_found("No user found for the specified ID."))
+}
+
+fn trimmed(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string())
+}
+
+pub fn validate_profile(profile: ProfileUpdate) -> Result<ProfileUpdate, AppError> {
+    let profile = ProfileUpdate {
+        display_name: trimmed(profile.display_name),
+        phone: trimmed(profile.phone),
+        qualification: trimmed(profile.qualification),
+        registration_number: trimmed(profile.registration_number),
+        avatar_path: trimmed(profile.avatar_path),
+    };
+
+    if let Some(name) = &profile.display_name {
+        if name.chars().count() > 80 {
+            return Err(AppError::validation("display_name", "Display name must be at most 80 characters"));
         }
     }
-    Err("Invalid username or password".to_string())
+    if let Some(phone) = profile.phone.as_ref().filter(|p| !p.is_empty()) {
+        let digits = phone.chars().filter(|c| c.is_ascii_digit()).count();
+        let allowed = phone.chars().all(|c| c.is_ascii_digit() || " +-()".contains(c));
+        if !allowed || !(7..=15).contains(&digits) {
+            return Err(AppError::validation("phone", format!("'{}' is not a valid phone number", phone)));
+        }
+    }
+    let limited = [
+        ("qualification", "Qualification", &profile.qualification),
+        ("registration_number", "Registration number", &profile.registration_number),
+    ];
+    for (field, label, value) in limited {
+        if value.as_ref().is_some_and(|v| v.chars().count() > 40) {
+            return Err(AppError::validation(field, format!("{} must be at most 40 characters", label)));
+        }
+    }
+    if let Some(path) = profile.avatar_path.as_ref().filter(|p| !p.is_empty()) {
+        if !std::path::Path::new(path).is_file() {
+            return Err(AppError::validation("avatar_path", format!("Avatar file {} does not exist", path)));
+        }
+    }
+    Ok(profile)
+}
+
+// Usernames and emails are stored trimmed and lower-cased so lookups and the
+// unique indexes treat "Asha " and "asha" as the same account
+pub fn normalize_username(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn validate_username(username: &str) -> Result<(), AppError> {
+    let length = username.chars().count();
+    if !(3..=32).contains(&length) {
+        return Err(AppError::validation("username", "Username must be between 3 and 32 characters"));
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)) {
+        return Err(AppError::validation(
+            "username",
+            "Username may only contain letters, digits, '.', '_' and '-'",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_email(email: &str) -> Result<(), AppError> {
+    let invalid = || AppError::validation("email", format!("'{}' is not a valid email address", email));
+    let (local, domain) = email.split_once('@').ok_or_else(invalid)?;
+    let domain_ok = domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !domain.contains("..")
+        && domain.chars().all(|c| c.is_ascii_alphanumeric() || ".-".contains(c));
+    if local.is_empty() || local.contains(char::is_whitespace) || !domain_ok {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+// Turn a duplicate-key error from the unique indexes into a message for the signup form
+fn duplicate_user_error(error: mongodb::error::Error) -> AppError {
+    match duplicate_key_message(&error) {
+        Some(message) if message.contains("username") => AppError::conflict("Username already taken"),
+        Some(_) => AppError::conflict("Email already in use"),
+        None => error.into(),
+    }
+}
+
+// Normalise existing accounts and create the unique username and email indexes.
+// Accounts that only differ by case must be merged by hand before the index can be built.
+pub async fn ensure_user_indexes(db: &Database) -> Result<(), AppError> {
+    let users: Collection<User> = db.collection("users");
+    let normalize = vec![doc! { "$set": {
+        "username": { "$toLower": { "$trim": { "input": "$username" } } },
+        "email": { "$toLower": { "$trim": { "input": "$email" } } },
+    } }];
+    users.update_many(doc! {}, normalize, None).await?;
+
+    let unique = |field: &str| {
+        IndexModel::builder()
+            .keys(doc! { field: 1 })
+            .options(IndexOptions::builder().unique(true).name(format!("{}_unique", field)).build())
+            .build()
+    };
+    users
+        .create_indexes(vec![unique("username"), unique("email")], None)
+        .await
+        .map_err(|e| {
+            log::error!("Failed to create unique user indexes: {}", e);
+            AppError::internal("Failed to create unique user indexes")
+        })?;
+    Ok(())
 }
@@ -0,0 +1,135 @@
+use chrono::NaiveDate;
+use mongodb::bson::doc;
+use mongodb::options::IndexOptions;
+use mongodb::{Collection, Database, IndexModel};
+use crate::commands::Medicine;
+use crate::error::{is_duplicate_key, AppError, FieldError};
+use crate::settings::StoreSettings;
+
+// Rules a medicine batch is checked against, taken from the store settings
+pub struct MedicineRules {
+    pub allow_selling_below_cost: bool, // Accept with a warning instead of rejecting
+    pub max_selling_price: Option<f64>,
+}
+
+impl From<&StoreSettings> for MedicineRules {
+    fn from(settings: &StoreSettings) -> Self {
+        MedicineRules {
+            allow_selling_below_cost: settings.allow_selling_below_cost,
+            max_selling_price: settings.max_selling_price,
+        }
+    }
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+}
+
+// Check every field of a batch, reporting all problems at once. Returns warnings
+// for things the store allows but should know about.
+pub fn validate_medicine(medicine: &Medicine, rules: &MedicineRules) -> Result<Vec<String>, AppError> {
+    let mut errors: Vec<FieldError> = Vec::new();
+    let mut fail = |field: &str, message: String| {
+        errors.push(FieldError { field: field.to_string(), message });
+    };
+    let mut warnings = Vec::new();
+
+    for (field, label, value, max) in [
+        ("name", "Name", &medicine.name, 120),
+        ("batch_number", "Batch number", &medicine.batch_number, 40),
+        ("wholesaler_name", "Wholesaler", &medicine.wholesaler_name, 120),
+    ] {
+        if value.trim().is_empty() {
+            fail(field, format!("{} is required", label));
+        } else if value.chars().count() > max {
+            fail(field, format!("{} must be at most {} characters", label, max));
+        }
+    }
+
+    let expiry = parse_date(&medicine.expiry_date);
+    let purchased = parse_date(&medicine.purchase_date);
+    if expiry.is_none() {
+        fail("expiry_date", "Expiry date must be a date in YYYY-MM-DD format".to_string());
+    }
+    if purchased.is_none() {
+        fail("purchase_date", "Purchase date must be a date in YYYY-MM-DD format".to_string());
+    }
+    if let (Some(expiry), Some(purchased)) = (expiry, purchased) {
+        if expiry <= purchased {
+            fail("expiry_date", "Expiry date must be after the purchase date".to_string());
+        }
+    }
+
+    let purchase_ok = medicine.purchase_price.is_finite() && medicine.purchase_price >= 0.0;
+    let selling_ok = medicine.selling_price.is_finite() && medicine.selling_price > 0.0;
+    if !purchase_ok {
+        fail("purchase_price", "Purchase price must be zero or a positive amount".to_string());
+    }
+    if !selling_ok {
+        fail("selling_price", "Selling price must be a positive amount".to_string());
+    }
+    if purchase_ok && selling_ok && medicine.selling_price < medicine.purchase_price {
+        let message = format!(
+            "Selling price {:.2} is below the purchase price {:.2}",
+            medicine.selling_price, medicine.purchase_price
+        );
+        if rules.allow_selling_below_cost {
+            warnings.push(message);
+        } else {
+            fail("selling_price", message);
+        }
+    }
+    if let Some(max) = rules.max_selling_price {
+        if selling_ok && medicine.selling_price > max {
+            fail("selling_price", format!("Selling price cannot exceed the maximum of {:.2}", max));
+        }
+    }
+
+    if errors.is_empty() {
+        return Ok(warnings);
+    }
+    Err(AppError::Validation {
+        message: if errors.len() == 1 {
+            errors[0].message.clone()
+        } else {
+            format!("{} fields are invalid", errors.len())
+        },
+        fields: errors,
+    })
+}
+
+pub const BATCH_INDEX: &str = "store_product_batch_active_unique";
+
+// Name a clash with the store/product/batch unique index
+pub fn duplicate_batch_error(error: mongodb::error::Error, medicine: &Medicine) -> AppError {
+    if is_duplicate_key(&error) {
+        return AppError::conflict(format!(
+            "Batch {} of {} already exists",
+            medicine.batch_number, medicine.name
+        ));
+    }
+    error.into()
+}
+
+// One batch number per product per store among batches that are not in the trash,
+// so a batch can be entered again after the old entry was deleted
+pub async fn ensure_medicine_indexes(db: &Database) -> Result<(), AppError> {
+    let medicines: Collection<Medicine> = db.collection("medicines");
+    let options = IndexOptions::builder()
+        .unique(true)
+        .name(BATCH_INDEX.to_string())
+        .partial_filter_expression(doc! { "deleted_at": { "$exists": false } })
+        .build();
+    let index = IndexModel::builder()
+        .keys(doc! { "user_id": 1, "name": 1, "batch_number": 1 })
+        .options(options)
+        .build();
+    medicines
+        .create_index(index, None)
+        .await
+        .map_err(|e| {
+            log::error!("Failed to create the medicine batch index: {}", e);
+            AppError::internal("Failed to create the medicine batch index")
+        })?;
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, HashMap};
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+use crate::billing::{local_day_bounds, round2};
+use crate::commands::Medicine;
+use crate::db::DbState;
+use crate::error::AppError;
+use crate::ledger::movements_between;
+use crate::session::{require_operator, SessionState};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValuationRow {
+    pub key: String, // Product name, supplier or category depending on the grouping
+    pub quantity: i64,
+    pub cost_value: f64,
+    pub retail_value: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValuationReport {
+    pub as_of: Option<String>, // None means current stock
+    pub method: String,
+    pub group_by: String,
+    pub rows: Vec<ValuationRow>,
+    pub total_quantity: i64,
+    pub total_cost_value: f64,
+    pub total_retail_value: f64,
+}
+
+// A batch as it stood on the valuation date
+struct BatchPosition {
+    name: String,
+    supplier: String,
+    category: String,
+    purchase_date: String,
+    unit_cost: f64,
+    selling_price: f64,
+    on_hand: i64,
+    received: i64, // Units booked in up to the valuation date
+}
+
+impl BatchPosition {
+    fn key(&self, group_by: &str) -> String {
+        match group_by {
+            "supplier" => self.supplier.clone(),
+            "category" => self.category.clone(),
+            _ => self.name.clone(),
+        }
+    }
+}
+
+// Value stock at cost and at selling price using FIFO or weighted-average costing.
+// With `as_of`, quantities are rolled back through the stock ledger to the end of that day.
+#[command]
+pub async fn get_inventory_valuation(
+    user_id: String,
+    method: String,
+    group_by: String,
+    as_of: Option<String>,
+    db: State<'_, DbState>,
+    session: State<'_, SessionState>,
+) -> Result<ValuationReport, AppError> {
+    require_operator(&db.db, &session, &user_id).await?;
+    if !matches!(method.as_str(), "fifo" | "weighted_average") {
+        return Err(AppError::validation(
+            "method",
+            format!("Unknown valuation method '{}', expected fifo or weighted_average", method),
+        ));
+    }
+    if !matches!(group_by.as_str(), "product" | "supplier" | "category") {
+        return Err(AppError::validation("group_by", format!("Cannot group valuation by '{}'", group_by)));
+    }
+    let cutoff = match &as_of {
+        Some(date) => Some(local_day_bounds(date)?.1),
+        None => None,
+    };
+
+    let collection: Collection<Medicine> = db.db.collection("medicines");
+    let medicines: Vec<Medicine> = collection
+        .find(doc! { "user_id": &user_id }, None)
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut batches: HashMap<String, BatchPosition> = medicines
+        .into_iter()
+        .filter_map(|m| {
+            let id = m.id?.to_hex();
+            Some((id, BatchPosition {
+                name: m.name,
+                supplier: m.wholesaler_name,
+                category: m.category.unwrap_or_else(|| "Uncategorised".to_string()),
+                purchase_date: m.purchase_date,
+                unit_cost: m.purchase_price,
+                selling_price: m.selling_price,
+                // A trashed batch's stock left the ledger when it was deleted
+                on_hand: if m.deleted_at.is_some() { 0 } else { m.quantity as i64 },
+                received: 0,
+            }))
+        })
+        .collect();
+
+    // Undo every movement after the cutoff; batches deleted since then come back from the ledger
+    if let Some(cutoff) = cutoff {
+        for movement in movements_between(&db.db, &user_id, Some(cutoff), None).await? {
+            let batch = batches.entry(movement.medicine_id.clone()).or_insert_with(|| BatchPosition {
+                name: movement.name.clone(),
+                supplier: movement.wholesaler_name.clone(),
+                category: movement.category.clone().unwrap_or_else(|| "Uncategorised".to_string()),
+                purchase_date: String::new(),
+                unit_cost: movement.unit_cost,
+                selling_price: movement.selling_price,
+                on_hand: 0,
+                received: 0,
+            });
+            batch.on_hand -= movement.change;
+        }
+    }
+    for movement in movements_between(&db.db, &user_id, None, cutoff).await? {
+        if movement.change > 0 && movement.reason == "receipt" {
+            if let Some(batch) = batches.get_mut(&movement.medicine_id) {
+                batch.received += movement.change;
+            }
+        }
+    }
+
+    // Batches recorded before the ledger existed have no receipt, so use what is on hand
+    let mut products: HashMap<String, Vec<BatchPosition>> = HashMap::new();
+    for mut batch in batches.into_values() {
+        batch.on_hand = batch.on_hand.max(0);
+        batch.received = batch.received.max(batch.on_hand);
+        if batch.received > 0 {
+            products.entry(batch.name.clone()).or_default().push(batch);
+        }
+    }
+
+    let mut rows: BTreeMap<String, ValuationRow> = BTreeMap::new();
+    for mut product_batches in products.into_values() {
+        let on_hand: i64 = product_batches.iter().map(|b| b.on_hand).sum();
+
+        // Cost attributed to each batch of this product
+        let costs: Vec<f64> = if method == "fifo" {
+            // Oldest stock is sold first, so what remains comes from the newest receipts
+            product_batches.sort_by(|a, b| b.purchase_date.cmp(&a.purchase_date));
+            let mut remaining = on_hand;
+            product_batches
+                .iter()
+                .map(|batch| {
+                    let taken = remaining.min(batch.received);
+                    remaining -= taken;
+                    taken as f64 * batch.unit_cost
+                })
+                .collect()
+        } else {
+            let received: i64 = product_batches.iter().map(|b| b.received).sum();
+            let received_cost: f64 = product_batches.iter().map(|b| b.received as f64 * b.unit_cost).sum();
+            let average = if received > 0 { received_cost / received as f64 } else { 0.0 };
+            product_batches.iter().map(|b| b.on_hand as f64 * average).collect()
+        };
+
+        for (batch, cost) in product_batches.iter().zip(costs) {
+            let key = batch.key(&group_by);
+            let row = rows.entry(key.clone()).or_insert_with(|| ValuationRow {
+                key,
+                quantity: 0,
+                cost_value: 0.0,
+                retail_value: 0.0,
+            });
+            row.quantity += batch.on_hand;
+            row.cost_value += cost;
+            row.retail_value += batch.on_hand as f64 * batch.selling_price;
+        }
+    }
+
+    let rows: Vec<ValuationRow> = rows
+        .into_values()
+        .filter(|row| row.quantity > 0 || row.cost_value > 0.0)
+        .map(|row| ValuationRow {
+            cost_value: round2(row.cost_value),
+            retail_value: round2(row.retail_value),
+            ..row
+        })
+        .collect();
+
+    Ok(ValuationReport {
+        as_of,
+        total_quantity: rows.iter().map(|r| r.quantity).sum(),
+        total_cost_value: round2(rows.iter().map(|r| r.cost_value).sum()),
+        total_retail_value: round2(rows.iter().map(|r| r.retail_value).sum()),
+        method,
+        group_by,
+        rows,
+    })
+}
@@ -2,6 +2,8 @@ import { useState, useEffect } from 'react';
 import { toast } from 'sonner';
 import { searchMedicines } from '../hooks/searchMedicines';
 import { printBill } from '../hooks/printBill';
+import { getStoreSettings, StoreSettings } from '../hooks/storeSettings';
+import { useAuth } from '../context/AuthContext';
 import BillingSummary from './BillingSummary';
 import debounce from 'lodash.debounce';
 
@@ -11,6 +13,8 @@ export type MedicineInfo = {
 };
 
 const Billing = () => {
+  const { userId } = useAuth();
+  const [store, setStore] = useState<StoreSettings | null>(null);
   const [query, setQuery] = useState('');
   const [searchResults, setSearchResults] = useState<MedicineInfo[]>([]);
   const [selectedMedicines, setSelectedMedicines] = useState<
@@ -20,6 +24,13 @@ const Billing = () => {
   const [openDialog, setOpenDialog] = useState(false);
   const [billingId] = useState(Math.floor(Math.random() * 100000)); // Generate random billing ID
 
+  // Load the store's name and address for the heading and the printed bill
+  useEffect(() => {
+    if (userId) {
+      getStoreSettings(userId).then(setStore);
+    }
+  }, [userId]);
+
   // Effect to trigger search on query change
   useEffect(() => {
     const handleSearch = async () => {
@@ -56,13 +67,13 @@ const Billing = () => {
   // Function to handle confirm purchase
   const handleConfirmPurchase = () => {
     setOpenDialog(false);
-    printBill(selectedMedicines, customerName, billingId); // Pass selected medicines, customer name, and billing ID
+    printBill(selectedMedicines, customerName, billingId, store); // Pass selected medicines, customer name, and billing ID
   };
 
   return (
     <div className="mx-auto p-4 border border-gray-300 rounded-lg h-full relative">
       <div className='font-bold text-2xl'>
-        ABC Pharmacy and Hospital
+        {store?.legal_name}
       </div>
       <div className="mb-4 mt-5">
         <input
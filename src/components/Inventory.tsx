@@ -1,28 +1,36 @@
 import React, { useState, useEffect } from 'react';
 import { invoke } from '@tauri-apps/api/core';// Tauri API to call backend Rust functions
+import { useAuth } from '../context/AuthContext';
 
-// Define a TypeScript interface for the Medicine data type
-interface Medicine {
-  id: number;
+// One product's stock summed over its batches, as returned by `get_inventory`
+interface InventoryItem {
   name: string;
-  stock: number;
-  expiryDate: string; // Date can also be handled as string for simplicity
+  total_quantity: number;
+  nearest_expiry: string | null;
+  value_at_cost: number;
+  value_at_sale: number;
+  reorder_level: number;
+  reorder_status: 'out_of_stock' | 'reorder' | 'ok';
 }
 
 const Inventory: React.FC = () => {
-  const [medicines, setMedicines] = useState<Medicine[]>([]); // Use the Medicine interface
+  const [items, setItems] = useState<InventoryItem[]>([]);
+  const { userId } = useAuth();
 
   useEffect(() => {
+    if (!userId) {
+      return;
+    }
     async function fetchData() {
       try {
-        const response: Medicine[] = await invoke<Medicine[]>('get_inventory'); // Fetch inventory data from backend
-        setMedicines(response);
+        const response = await invoke<InventoryItem[]>('get_inventory', { userId }); // Fetch inventory data from backend
+        setItems(response);
       } catch (error) {
         console.error('Failed to fetch inventory:', error);
       }
     }
     fetchData();
-  }, []);
+  }, [userId]);
 
   return (
     <div>
@@ -32,15 +40,21 @@ const Inventory: React.FC = () => {
           <tr>
             <th>Medicine Name</th>
             <th>Stock</th>
-            <th>Expiry Date</th>
+            <th>Nearest Expiry</th>
+            <th>Value at Cost</th>
+            <th>Value at Sale</th>
+            <th>Reorder</th>
           </tr>
         </thead>
         <tbody>
-          {medicines.map((medicine) => (
-            <tr key={medicine.id}>
-              <td>{medicine.name}</td>
-              <td>{medicine.stock}</td>
-              <td>{medicine.expiryDate}</td>
+          {items.map((item) => (
+            <tr key={item.name}>
+              <td>{item.name}</td>
+              <td>{item.total_quantity}</td>
+              <td>{item.nearest_expiry ?? '-'}</td>
+              <td>{item.value_at_cost.toFixed(2)}</td>
+              <td>{item.value_at_sale.toFixed(2)}</td>
+              <td>{item.reorder_status === 'ok' ? '' : item.reorder_status.replace(/_/g, ' ')}</td>
             </tr>
           ))}
         </tbody>
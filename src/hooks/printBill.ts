@@ -1,10 +1,12 @@
 import { MedicineInfo } from "@/components/Billing";
+import { StoreSettings } from "./storeSettings";
 
 // Function to print the bill
 export const printBill = (
   selectedMedicines: { medicine: MedicineInfo; quantity: number }[],
   customerName: string,
-  billingId: number
+  billingId: number,
+  store: StoreSettings | null
 ) => {
   const printWindow = window.open('', '', 'height=600,width=800');
   if (printWindow) {
@@ -12,6 +14,16 @@ export const printBill = (
     const totalCost = selectedMedicines.reduce((total, item) =>
       total + item.medicine.selling_price * item.quantity, 0);
 
+    // Store details come from the store settings; lines that are not set are left out
+    const addressLines = [
+      ...(store?.address ?? '').split('\n'),
+      store?.phone ? `Phone: ${store.phone}` : '',
+      store?.email ? `Email: ${store.email}` : '',
+      store?.drug_licence_numbers.length ? `D.L. No: ${store.drug_licence_numbers.join(', ')}` : '',
+      store?.gstin ? `GSTIN: ${store.gstin}` : '',
+    ].map(line => line.trim()).filter(line => line !== '');
+    const footerLines = (store?.invoice_footer ?? '').split('\n').map(line => line.trim()).filter(line => line !== '');
+
     printWindow.document.write(`
       <html>
         <head>
@@ -94,12 +106,9 @@ export const printBill = (
         <body>
           <div class="header">
             <img src="logo.jpg" alt="Pharmacy Logo" /> <!-- Add your logo image here -->
-            <h2>ABC Pharmacy and Hospital</h2>
+            <h2>${store?.legal_name ?? ''}</h2>
             <p class="address">
-              1234 Health St.<br>
-              Cityville, ST 12345<br>
-              Phone: (123) 456-7890<br>
-              Email: info@abcpharmacy.com
+              ${addressLines.join('<br>')}
             </p>
           </div>
 
@@ -139,8 +148,7 @@ export const printBill = (
           </table>
           <div class="total">Total Cost: $${totalCost.toFixed(2)}</div>
           <footer>
-            Thank you for choosing ABC Pharmacy and Hospital!<br />
-            Please retain this receipt for your records.
+            ${footerLines.join('<br />')}
           </footer>
         </body>
       </html>
@@ -0,0 +1,22 @@
+import { invoke } from '@tauri-apps/api/core';
+
+// Store identity printed on bills, as saved with update_store_settings
+export type StoreSettings = {
+  legal_name: string;
+  address: string;
+  phone?: string | null;
+  email?: string | null;
+  drug_licence_numbers: string[];
+  gstin?: string | null;
+  invoice_footer: string;
+};
+
+// Function to fetch the store settings of the logged in store
+export const getStoreSettings = async (userId: string): Promise<StoreSettings | null> => {
+  try {
+    return await invoke<StoreSettings>('get_store_settings', { userId });
+  } catch (error) {
+    console.error('Failed to fetch store settings:', error);
+    return null;
+  }
+};